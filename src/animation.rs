@@ -0,0 +1,287 @@
+// keyframed animations
+// a keyframe file has one keyframe per line, `t re im radius angle max_iter palette_offset [interp]`,
+// with # comments. interp (linear, ease or catmull) is how the segment starting at that keyframe
+// moves and defaults to linear. the radius is interpolated in log space so zooms run at a constant speed
+
+use std::collections::VecDeque;
+
+use image::Rgb32FImage;
+
+use crate::utils::*;
+use crate::params::ViewParams;
+use crate::grid::Grid;
+use crate::render::Renderer;
+use crate::mandelbrot::Mapping;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interp {
+    Linear,
+    /// smoothstep, slowing into and out of each keyframe
+    Ease,
+    /// catmull-rom through the neighbouring keyframes
+    Catmull,
+}
+impl Interp {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "linear" => Some(Interp::Linear),
+            "ease" => Some(Interp::Ease),
+            "catmull" => Some(Interp::Catmull),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub t: f32,
+    pub centre: Complex,
+    pub radius: f32,
+    pub angle: f32,
+    pub max_iter: usize,
+    pub palette_offset: f32,
+    pub interp: Interp,
+}
+impl Keyframe {
+    /// the interpolated quantities, radius as its log
+    fn values(&self) -> [f32; 6] {
+        [self.centre.real, self.centre.imag, self.radius.ln(), self.angle, self.max_iter as f32, self.palette_offset]
+    }
+}
+
+/// keyframes sorted by time, at least two of them
+pub fn parse_keyframes(s: &str) -> Result<Vec<Keyframe>, String> {
+    let mut keys = Vec::new();
+    for (n, l) in s.lines().enumerate() {
+        let l = l.split('#').next().unwrap_or("").trim();
+        if l.is_empty() {
+            continue
+        }
+        let bad = || format!("line {}: expected `t re im radius angle max_iter palette_offset [linear|ease|catmull]`", n + 1);
+        let f: Vec<_> = l.split_ascii_whitespace().collect();
+        if f.len() != 7 && f.len() != 8 {
+            return Err(bad())
+        }
+        let num = |i: usize| f[i].parse::<f32>().map_err(|_| bad());
+        let k = Keyframe {
+            t: num(0)?,
+            centre: Complex { real: num(1)?, imag: num(2)? },
+            radius: num(3)?,
+            angle: num(4)?,
+            max_iter: f[5].parse().map_err(|_| bad())?,
+            palette_offset: num(6)?,
+            interp: f.get(7).map_or(Some(Interp::Linear), |i| Interp::from_name(i)).ok_or_else(bad)?,
+        };
+        if k.radius <= 0.0 {
+            return Err(format!("line {}: radius must be positive", n + 1))
+        }
+        keys.push(k);
+    }
+    if keys.len() < 2 {
+        return Err(String::from("an animation needs at least two keyframes"))
+    }
+    keys.sort_by(|a, b| a.t.total_cmp(&b.t));
+    Ok(keys)
+}
+
+/// the time of each of frames frames, evenly spaced from the first keyframe to the last
+pub fn frame_times(keys: &[Keyframe], frames: usize) -> Vec<f32> {
+    let (t0, t1) = (keys[0].t, keys[keys.len() - 1].t);
+    (0..frames).map(|n| {
+        if frames == 1 { t0 } else { lerp(n as f32 / (frames - 1) as f32, t0, t1) }
+    }).collect()
+}
+
+/// times of samples sub-frames spread over shutter degrees of the frame interval dt, centred on t
+/// a 360 degree shutter stays open for the whole interval
+pub fn shutter_times(t: f32, dt: f32, shutter: f32, samples: usize) -> Vec<f32> {
+    let open = dt * shutter / 360.0;
+    (0..samples).map(|k| t + open * ((k as f32 + 0.5) / samples as f32 - 0.5)).collect()
+}
+
+/// the view at time t, with everything the keyframes don't set taken from base
+pub fn view_at(keys: &[Keyframe], t: f32, base: &ViewParams) -> ViewParams {
+    let last = keys.len() - 1;
+    let i = keys.iter().rposition(|k| k.t <= t).unwrap_or(0).min(last - 1);
+    let (a, b) = (&keys[i], &keys[i + 1]);
+    let u = if b.t > a.t { ((t - a.t) / (b.t - a.t)).clamp(0.0, 1.0) } else { 1.0 };
+
+    let v: [f32; 6] = match a.interp {
+        Interp::Linear => std::array::from_fn(|j| lerp(u, a.values()[j], b.values()[j])),
+        Interp::Ease => {
+            let u = u * u * (3.0 - 2.0 * u);
+            std::array::from_fn(|j| lerp(u, a.values()[j], b.values()[j]))
+        }
+        Interp::Catmull => {
+            let p0 = keys[i.saturating_sub(1)].values();
+            let p3 = keys[(i + 2).min(last)].values();
+            std::array::from_fn(|j| catmull_rom(u, p0[j], a.values()[j], b.values()[j], p3[j]))
+        }
+    };
+    ViewParams {
+        centre: Complex { real: v[0], imag: v[1] },
+        radius: v[2].exp(),
+        angle: v[3],
+        max_iter: v[4].round().max(1.0) as usize,
+        palette_offset: v[5],
+        .. base.clone()
+    }
+}
+
+/// how colouring carries over between frames, so it doesn't flicker as the histogram shifts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourLock {
+    /// each frame uses its own renderer's mapping
+    Off,
+    /// every frame uses the colouring table of this frame
+    Reference(usize),
+    /// the renderer's mapping if it doesn't depend on the image, log in place of histogram
+    Fixed,
+    /// the histogram averaged over this many frames up to the current one
+    Smooth(usize),
+}
+impl ColourLock {
+    pub fn name(self) -> String {
+        match self {
+            ColourLock::Off => String::from("off"),
+            ColourLock::Reference(n) => format!("reference {}", n),
+            ColourLock::Fixed => String::from("fixed"),
+            ColourLock::Smooth(n) => format!("smooth {}", n),
+        }
+    }
+}
+
+/// colouring state for one animation
+pub struct Colouring {
+    lock: ColourLock,
+    reference: Option<Vec<f32>>,
+    recent: VecDeque<Vec<f32>>,
+}
+impl Colouring {
+    pub fn new(lock: ColourLock) -> Self {
+        Self { lock, reference: None, recent: VecDeque::new() }
+    }
+    /// renders a frame from the renderers of its sub-frames, all the same size
+    /// every sub-frame's samples go through the filter into one accumulation per pixel, so the
+    /// frame is resolved once, the same as the extra samples of antialiasing
+    /// the middle sub-frame picks the colouring table and the rest share it
+    /// reference gives the reference frame's renderer, and is only called once
+    pub fn render(&mut self, rs: &[Renderer], reference: impl FnOnce() -> Result<Renderer, String>) -> Result<Rgb32FImage, String> {
+        let m = rs.len() / 2;
+        let mid = &rs[m];
+        if rs.len() == 1 && self.lock == ColourLock::Off {
+            return Ok(mid.render())
+        }
+        if matches!(self.lock, ColourLock::Reference(_)) && self.reference.is_none() {
+            self.reference = Some(reference()?.prepass_histogram())
+        }
+
+        let (w, hi) = (mid.width, mid.height);
+        let mut acc = vec![Accum::default(); w * hi];
+        let g = mid.sample_rows(0, hi);
+        let h = self.table(mid, &g);
+        mid.accumulate_rows(&g, 0, &h, 0, hi, &mut acc);
+        for r in rs[..m].iter().chain(&rs[m + 1..]) {
+            r.accumulate_rows(&r.sample_rows(0, hi), 0, &fit_table(&h, r.max_iter as usize), 0, hi, &mut acc)
+        }
+        Ok(Rgb32FImage::from_fn(w as u32, hi as u32, |x, y| acc[x as usize + y as usize * w].resolve()))
+    }
+
+    fn table(&mut self, r: &Renderer, g: &Grid<u32>) -> Vec<f32> {
+        let len = r.max_iter as usize;
+        match self.lock {
+            ColourLock::Off => r.mapping.table(g, r.max_iter),
+            ColourLock::Fixed => r.mapping.fixed(r.max_iter).or_else(|| Mapping::Log.fixed(r.max_iter)).unwrap_or_default(),
+            ColourLock::Reference(_) => fit_table(self.reference.as_deref().unwrap_or_default(), len),
+            ColourLock::Smooth(n) => {
+                self.recent.push_back(r.mapping.table(g, r.max_iter));
+                while self.recent.len() > n.max(1) {
+                    self.recent.pop_front();
+                }
+                average_tables(self.recent.iter(), len)
+            }
+        }
+    }
+}
+
+/// a colouring table from a render with another max_iter, counts past its end get the end of the palette
+fn fit_table(h: &[f32], len: usize) -> Vec<f32> {
+    (0..len).map(|i| h.get(i).copied().unwrap_or(1.0)).collect()
+}
+fn average_tables<'a>(tables: impl ExactSizeIterator<Item = &'a Vec<f32>>, len: usize) -> Vec<f32> {
+    let n = tables.len().max(1) as f32;
+    let mut sum = vec![0.0; len];
+    for t in tables {
+        sum.iter_mut().zip(fit_table(t, len)).for_each(|(s, v)| *s += v / n)
+    }
+    sum
+}
+
+fn catmull_rom(u: f32, p0: f32, p1: f32, p2: f32, p3: f32) -> f32 {
+    let (u2, u3) = (u * u, u * u * u);
+    0.5 * ((2.0 * p1) + (p2 - p0) * u + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u2 + (3.0 * p1 - p0 - 3.0 * p2 + p3) * u3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const KEYS: &str = "
+        # t re im radius angle iters offset
+        0 -0.5 0 2 0 100 0 catmull
+        1 -0.7 0.2 0.02 0.5 300 0.5 ease
+        2 -0.75 0.1 0.0002 1 1000 1
+    ";
+    #[test]
+    fn passes_through_keyframes() {
+        let keys = parse_keyframes(KEYS).unwrap();
+        let base = ViewParams::default();
+        for k in &keys {
+            let v = view_at(&keys, k.t, &base);
+            assert!((v.radius / k.radius - 1.0).abs() < 1e-4, "{} {}", v.radius, k.radius);
+            assert!((v.centre.real - k.centre.real).abs() < 1e-6);
+            assert_eq!(v.max_iter, k.max_iter);
+        }
+    }
+    #[test]
+    fn shutter_centres_on_frame() {
+        assert_eq!(shutter_times(1.0, 0.5, 180.0, 2), [0.9375, 1.0625]);
+        assert_eq!(shutter_times(1.0, 0.5, 0.0, 3), [1.0; 3]);
+    }
+    #[test]
+    fn averages_tables_of_any_length() {
+        let (a, b) = (vec![0.0, 0.5], vec![0.5, 1.0, 1.0, 1.0]);
+        assert_eq!(average_tables([&a, &b].into_iter(), 3), [0.25, 0.75, 1.0]);
+    }
+    #[test]
+    fn fixed_lock_keeps_fixed_mappings() {
+        let p = ViewParams { width: 4, height: 4, max_iter: 10, mapping: Mapping::Linear, .. ViewParams::default() };
+        let g = Grid::new(4, 4, 0u32);
+        let mut c = Colouring::new(ColourLock::Fixed);
+        assert_eq!(c.table(&Renderer::new(&p).unwrap(), &g), Mapping::Linear.fixed(10).unwrap());
+        let p = ViewParams { mapping: Mapping::Histogram, .. p };
+        assert_eq!(c.table(&Renderer::new(&p).unwrap(), &g), Mapping::Log.fixed(10).unwrap());
+    }
+    #[test]
+    fn blends_sub_frames_in_linear_light() {
+        // one sub-frame inside the set and one far outside it
+        let p = ViewParams { width: 4, height: 4, radius: 0.01, angle: 0.0, mapping: Mapping::Linear, .. ViewParams::default() };
+        let rs = [Complex::ZERO, Complex { real: 10.0, imag: 0.0 }]
+            .map(|centre| Renderer::new(&ViewParams { centre, .. p.clone() }).unwrap());
+        let img = Colouring::new(ColourLock::Off).render(&rs, || unreachable!()).unwrap();
+        let (a, b) = (rs[0].render(), rs[1].render());
+        let mut acc = Accum::default();
+        acc.add(a.get_pixel(1, 2).0.map(srgb_to_linear), 1.0);
+        acc.add(b.get_pixel(1, 2).0.map(srgb_to_linear), 1.0);
+        assert_ne!(a.get_pixel(1, 2), b.get_pixel(1, 2));
+        assert!(img.get_pixel(1, 2).0.iter().zip(acc.resolve().0).all(|(u, v)| (u - v).abs() < 1e-5), "{:?}", img.get_pixel(1, 2));
+    }
+    #[test]
+    fn zooms_in_log_space() {
+        let keys = parse_keyframes(KEYS).unwrap();
+        // halfway through an ease segment is halfway in log radius
+        let v = view_at(&keys, 1.5, &ViewParams::default());
+        assert!((v.radius / 0.002 - 1.0).abs() < 1e-3, "{}", v.radius);
+        assert_eq!(frame_times(&keys, 5), [0.0, 0.5, 1.0, 1.5, 2.0]);
+        assert!(parse_keyframes("0 0 0 1 0 100 0").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use image::Rgb32FImage;
+
+use std::path::PathBuf;
+
+use crate::params::ViewParams;
+use crate::pixelmapper::PixelMapper;
+use crate::{mandelbrot, output};
+
+const THUMB_WIDTH: u32 = 160;
+/// thumbnails don't need a deep render to be recognisable
+const THUMB_MAX_ITER: usize = 500;
+
+/// bookmarks live in the per-user data dir, one png per bookmark
+/// the thumbnail carries the view in its text chunks, so there's nothing else to keep in sync
+pub struct Bookmarks {
+    dir: PathBuf,
+}
+impl Bookmarks {
+    pub fn open() -> Result<Self, String> {
+        let base = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+            .ok_or("couldn't find a data directory (set XDG_DATA_HOME or HOME)")?;
+        let dir = base.join("fractal_window").join("bookmarks");
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, name: &str) -> Result<String, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("bad bookmark name {:?}, use letters, digits, - and _", name))
+        }
+        Ok(self.dir.join(format!("{}.png", name)).to_string_lossy().into_owned())
+    }
+
+    pub fn add(&self, name: &str, params: &ViewParams) -> Result<(), String> {
+        let path = self.path(name)?;
+        output::save_image(&path, &thumbnail(params), params, 8)
+    }
+    pub fn get(&self, name: &str) -> Result<ViewParams, String> {
+        output::load_png_params(&self.path(name)?)
+    }
+    /// sorted bookmark names
+    pub fn list(&self) -> Result<Vec<String>, String> {
+        let mut v: Vec<_> = std::fs::read_dir(&self.dir).map_err(|e| e.to_string())?
+            .filter_map(|e| {
+                let name = e.ok()?.file_name().into_string().ok()?;
+                Some(name.strip_suffix(".png")?.to_owned())
+            })
+            .collect();
+        v.sort();
+        Ok(v)
+    }
+
+    /// tiles every thumbnail into one image, left-right then top-bottom in list order
+    pub fn export_sheet(&self, path: &str) -> Result<Vec<String>, String> {
+        let names = self.list()?;
+        if names.is_empty() {
+            return Err(String::from("no bookmarks"))
+        }
+        let thumbs = names.iter()
+            .map(|n| image::open(self.path(n)?).map(|i| i.into_rgb8()).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let cols = (thumbs.len() as f32).sqrt().ceil() as u32;
+        let rows = (thumbs.len() as u32).div_ceil(cols);
+        let cw = thumbs.iter().map(|t| t.width()).max().unwrap_or(1);
+        let ch = thumbs.iter().map(|t| t.height()).max().unwrap_or(1);
+        let mut sheet = image::RgbImage::new(cols * cw, rows * ch);
+        for (n, t) in thumbs.iter().enumerate() {
+            let n = n as u32;
+            image::imageops::replace(&mut sheet, t, ((n % cols) * cw) as i64, ((n / cols) * ch) as i64);
+        }
+        sheet.save(path).map_err(|e| e.to_string())?;
+        Ok(names)
+    }
+}
+
+fn thumbnail(p: &ViewParams) -> Rgb32FImage {
+    let w = THUMB_WIDTH;
+    let h = ((THUMB_WIDTH * p.height) / p.width.max(1)).max(1);
+    let pm = PixelMapper::new_radx(p.centre, p.radius, p.angle, w, h);
+    let max_iter = p.max_iter.min(THUMB_MAX_ITER) as u32;
+    let (g, hist) = mandelbrot::mt_generate_tables(&pm, w as usize, h as usize, max_iter);
+    mandelbrot::colour_tables(&g, &hist, w, h, p.palette)
+}
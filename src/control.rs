@@ -1,11 +1,21 @@
 use winit::event_loop::{EventLoopProxy, EventLoopClosed};
-use image::{Rgb, RgbImage};
 
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Sender, Receiver};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::utils::*;
-use crate::pixelmapper::PixelMapper;
-use crate::mandelbrot;
+use crate::pixelmapper::{self, PixelMapper, Frame, Projection};
+use crate::mandelbrot::{self, Formula, Mapping};
+use crate::params::ViewParams;
+use crate::{output, formats, pyramid, server, rawdata, animation, video, expmap};
+use crate::dataexport::{self, DataFormat};
+use crate::animation::ColourLock;
+use crate::bookmarks::Bookmarks;
+use crate::history::History;
+use crate::render::{Renderer, Sampling, Filter};
+
+const HISTORY_LENGTH: usize = 100;
 
 struct FatProxy(Option<EventLoopProxy<ViewUpdate>>);
 impl FatProxy {
@@ -33,130 +43,620 @@ struct Controller {
     scale: f32,
     vw: u32,
     vh: u32,
+
+    /// how renders lay out pixels, the viewfinder always shows the linear view
+    projection: Projection,
+    formula: Formula,
+    palette: Palette,
+    palette_offset: f32,
+    mapping: Mapping,
+    max_iter: usize,
+    /// pick max_iter per render instead of using max_iter
+    auto_iters: bool,
+    aa: usize,
+    sampling: Sampling,
+    filter: Filter,
+    adaptive: Option<f32>,
+    out: String,
+    render_count: usize,
+    /// bits per channel for png output
+    depth: u8,
+    /// rows per strip for tiled rendering, 0 renders the whole image at once
+    tile_rows: usize,
+    /// save the raw samples next to each render, for recolouring
+    raw: bool,
+    /// frame rate for animations written as video
+    fps: u32,
+    /// whether gif and apng animations repeat
+    looping: bool,
+    colour_lock: ColourLock,
+    /// motion blur, in degrees of the frame interval the shutter stays open
+    shutter: f32,
+    /// sub-frames blended per animation frame when the shutter is open
+    time_samples: usize,
+
+    history: History<ViewState>,
+    /// the view as the tile server sees it, if one is running
+    served: Option<Arc<Mutex<ViewParams>>>,
+}
+
+/// the parts of the controller that view history tracks
+#[derive(Debug, Clone, Copy)]
+struct ViewState {
+    centre: Complex,
+    radius: f32,
+    angle: f32,
+    iw: u32,
+    ih: u32,
+    scale: f32,
 }
 
-pub fn control_loop(p: Option<EventLoopProxy<ViewUpdate>>) {
+/// runs commands from stdin and from anything else holding a sender (ie. the viewer's key bindings)
+/// returns once every sender is gone, so stdin hitting eof ends a headless session
+pub fn control_loop(p: Option<EventLoopProxy<ViewUpdate>>, tx: Sender<String>, rx: Receiver<String>) {
     let p = FatProxy(p);
     let mut controller = Controller::new(p);
-    let c = rustyline::config::Config::builder().auto_add_history(true).build();
 
-    let mut rl = rustyline::DefaultEditor::with_config(c).unwrap();
-    loop {
-        let s = rl.readline("> ").unwrap();
+    std::thread::spawn(move || {
+        let c = rustyline::config::Config::builder().auto_add_history(true).build();
+        let mut rl = rustyline::DefaultEditor::with_config(c).unwrap();
+        while let Ok(s) = rl.readline("> ") {
+            if tx.send(s).is_err() {
+                break
+            }
+        }
+    });
+
+    for s in rx {
         if let Some(c) = parse_line(&s) {
-            controller.do_command(c)
+            controller.do_command(c);
+            controller.publish()
         }
     }
 }
 
 impl Controller {
     fn new(proxy: FatProxy) -> Self {
+        let p = ViewParams::default();
         Self {
             proxy,
 
-            render_pm: PixelMapper::new_radx(Complex { real: -1.0, imag: 0.0 }, 1.0, 1.0, crate::STARTING_WIDTH, crate::STARTING_HEIGHT),
-            centre: Complex { real: -1.0, imag: 0.0 },
-            radius: 1.0,
-            angle: 1.0,
+            render_pm: PixelMapper::new_radx(p.centre, p.radius, p.angle, p.width, p.height),
+            centre: p.centre,
+            radius: p.radius,
+            angle: p.angle,
 
-            iw: crate::STARTING_WIDTH,
-            ih: crate::STARTING_HEIGHT,
+            iw: p.width,
+            ih: p.height,
 
-            scale: 1.0 / 3.0,
+            scale: 1.0 / p.scale_divisor,
             vw: crate::STARTING_WINDOW_WIDTH,
             vh: crate::STARTING_WINDOW_HEIGHT,
+
+            projection: p.projection,
+            formula: p.formula,
+            palette: p.palette,
+            palette_offset: p.palette_offset,
+            mapping: p.mapping,
+            max_iter: p.max_iter,
+            auto_iters: false,
+            aa: p.aa,
+            sampling: p.sampling,
+            filter: p.filter,
+            adaptive: p.adaptive,
+            out: String::from("output.png"),
+            render_count: 0,
+            depth: 8,
+            tile_rows: 0,
+            raw: false,
+            fps: 30,
+            looping: true,
+            colour_lock: ColourLock::Off,
+            shutter: 0.0,
+            time_samples: 1,
+
+            history: History::new(HISTORY_LENGTH),
+            served: None,
         }
     }
+
+    /// lets the tile server see the latest view
+    fn publish(&self) {
+        if let Some(s) = &self.served {
+            *s.lock().unwrap() = self.params()
+        }
+    }
+
+    fn view_state(&self) -> ViewState {
+        ViewState {
+            centre: self.centre, radius: self.radius, angle: self.angle,
+            iw: self.iw, ih: self.ih, scale: self.scale,
+        }
+    }
+    fn restore(&mut self, v: ViewState) {
+        self.centre = v.centre; self.radius = v.radius; self.angle = v.angle;
+        self.set_resolution(v.iw, v.ih, 1.0 / v.scale);
+    }
+    /// call before anything that moves the view, so it can be undone
+    fn record(&mut self) {
+        let v = self.view_state();
+        self.history.push(v)
+    }
+
+    fn params(&self) -> ViewParams {
+        ViewParams {
+            centre: self.centre,
+            radius: self.radius,
+            angle: self.angle,
+            projection: self.projection,
+
+            width: self.iw,
+            height: self.ih,
+            scale_divisor: 1.0 / self.scale,
+
+            formula: self.formula,
+            palette: self.palette,
+            palette_offset: self.palette_offset,
+            mapping: self.mapping,
+            max_iter: self.max_iter,
+            aa: self.aa,
+            sampling: self.sampling,
+            filter: self.filter,
+            adaptive: self.adaptive,
+        }
+    }
+    fn apply_params(&mut self, p: &ViewParams) {
+        self.projection = p.projection;
+        self.formula = p.formula;
+        self.palette = p.palette;
+        self.palette_offset = p.palette_offset;
+        self.mapping = p.mapping;
+        self.max_iter = p.max_iter;
+        self.aa = p.aa;
+        self.sampling = p.sampling;
+        self.filter = p.filter;
+        self.adaptive = p.adaptive;
+        self.centre = p.centre; self.radius = p.radius; self.angle = p.angle;
+        self.set_resolution(p.width, p.height, p.scale_divisor);
+    }
+
+    fn set_resolution(&mut self, x: u32, y: u32, sd: f32) {
+        let pm = PixelMapper::new_radx(self.centre, self.radius, self.angle, x, y);
+        self.render_pm = pm;
+        self.iw = x; self.ih = y;
+        let scale = 1.0 / sd;
+        self.scale = scale;
+        let vf_pm = pm.scale(scale);
+        let vw = (x as f32 * scale) as u32;
+        self.vw = vw;
+        let vh = (y as f32 * scale) as u32;
+        self.vh = vh;
+        let _ = self.proxy.send_event(ViewUpdate { pm: vf_pm, wi: vw, hi: vh });
+    }
+    fn set_view(&mut self, centre: Complex, radius: f32, angle: f32) {
+        self.set_pm(PixelMapper::new_radx(centre, radius, angle, self.iw, self.ih), centre, radius, angle)
+    }
+    /// pm should be the render mapping for the view centre, radius, angle
+    fn set_pm(&mut self, pm: PixelMapper, centre: Complex, radius: f32, angle: f32) {
+        //eprintln!("view changed");
+        self.render_pm = pm;
+        self.centre = centre; self.radius = radius; self.angle = angle;
+        let vf_pm = pm.scale(self.scale);
+        let _ = self.proxy.send_event(ViewUpdate { pm: vf_pm, wi: self.vw, hi: self.vh });
+    }
+
     fn do_command(&mut self, c: Command) {
 
         use Command::*;
         match c {
-            Render(name, max_iter, aa) => self.render(name, max_iter, aa),
+            Render(name, max_iter, aa) => {
+                let name = match name {
+                    Some(n) => n.to_owned(),
+                    None => {
+                        let n = expand_template(&self.out, self.render_count, self.centre, self.radius);
+                        self.render_count += 1;
+                        n
+                    }
+                };
+                let max_iter = match max_iter {
+                    Some(i) => i,
+                    None if self.auto_iters => {
+                        let i = mandelbrot::auto_max_iter(&self.render_pm, self.iw as usize, self.ih as usize, self.radius);
+                        println!("auto iters chose {} (`set iters {}` to pin it)", i, i);
+                        i as usize
+                    }
+                    None => self.max_iter
+                };
+                let aa = aa.unwrap_or(self.aa);
+                self.render(&name, &ViewParams { max_iter, aa, .. self.params() })
+            }
             Resolution(x, y, sd) => {
-                let pm = PixelMapper::new_radx(self.centre, self.radius, self.angle, x, y);
-                self.render_pm = pm;
-                self.iw = x; self.ih = y;
-                let scale = 1.0 / sd;
-                self.scale = scale;
-                let vf_pm = pm.scale(scale);
-                let vw = (x as f32 * scale) as u32;
-                self.vw = vw;
-                let vh = (y as f32 * scale) as u32;
-                self.vh = vh;
-                let _ = self.proxy.send_event(ViewUpdate { pm: vf_pm, wi: vw, hi: vh });
+                self.record();
+                self.set_resolution(x, y, sd)
+            }
+            View(centre, frame, angle) => {
+                self.record();
+                let angle = angle.unwrap_or(self.angle);
+                let pm = PixelMapper::new_frame(centre, frame, angle, self.iw, self.ih);
+                self.set_pm(pm, centre, frame.radx(self.iw, self.ih), angle)
+            }
+            Corners(a, b) => {
+                self.record();
+                let (centre, radius) = pixelmapper::corners_view(a, b, self.iw, self.ih);
+                self.set_pm(PixelMapper::new_corners(a, b, self.iw, self.ih), centre, radius, 0.0)
+            }
+            Zoom(factor, at) => {
+                self.record();
+                let radius = self.radius / factor;
+                // keep the point under the given pixel where it is
+                let centre = match at {
+                    Some((px, py)) => {
+                        let p = self.render_pm.map_f(px, py);
+                        p + (self.centre - p) / factor
+                    }
+                    None => self.centre
+                };
+                self.set_view(centre, radius, self.angle)
+            }
+            Pan(dx, dy) => {
+                self.record();
+                let dx = dx.to_pixels(self.iw);
+                let dy = dy.to_pixels(self.ih);
+                let centre = self.render_pm.map_f(self.iw as f32 / 2.0 + dx, self.ih as f32 / 2.0 + dy);
+                self.set_view(centre, self.radius, self.angle)
+            }
+            Rotate(degrees) => {
+                self.record();
+                self.set_view(self.centre, self.radius, self.angle + degrees.to_radians())
+            }
+            Goto(centre) => {
+                self.record();
+                self.set_view(centre, self.radius, self.angle)
+            }
+            Back => match self.history.back(self.view_state()) {
+                Some(v) => self.restore(v),
+                None => println!("nothing to go back to")
+            }
+            Forward => match self.history.forward(self.view_state()) {
+                Some(v) => self.restore(v),
+                None => println!("nothing to go forward to")
+            }
+            History => {
+                let print = |marker: &str, v: &ViewState| println!(
+                    "{} {} {} r {} a {} ({}x{})", marker, v.centre.real, v.centre.imag, v.radius, v.angle, v.iw, v.ih
+                );
+                self.history.past().for_each(|v| print(" ", v));
+                print(">", &self.view_state());
+                self.history.future().for_each(|v| print(" ", v));
+            }
+            Save(path) => {
+                match self.params().save(path) {
+                    Ok(()) => println!("saved to {}", path),
+                    Err(e) => println!("failed to save: {}", e)
+                }
+            }
+            Load(path) => {
+                let p = if output::is_png(path) {
+                    output::load_png_params(path)
+                }
+                else {
+                    ViewParams::load(path)
+                };
+                match p {
+                    Ok(p) => {
+                        self.record();
+                        self.apply_params(&p)
+                    }
+                    Err(e) => println!("failed to load: {}", e)
+                }
+            }
+            Import(path) => match formats::import(path, &self.params()) {
+                Ok((p, unmapped)) => {
+                    if !unmapped.is_empty() {
+                        println!("not imported: {}", unmapped.join(", "))
+                    }
+                    self.record();
+                    self.apply_params(&p)
+                }
+                Err(e) => println!("failed to import: {}", e)
             }
-            View(centre, radius, angle) => {
-                //eprintln!("view changed");
-                let pm = PixelMapper::new_radx(centre, radius, angle, self.iw, self.ih);
-                self.render_pm = pm;
-                let vf_pm = pm.scale(self.scale);
-                let _ = self.proxy.send_event(ViewUpdate { pm: vf_pm, wi: self.vw, hi: self.vh });
+            ExportData(format, path, size) => {
+                let (w, h) = size.unwrap_or((self.iw, self.ih));
+                match dataexport::export(format, path, &self.params(), w, h) {
+                    Ok(files) => println!("wrote {}", files.join(" ")),
+                    Err(e) => println!("export failed: {}", e)
+                }
+            }
+            Export(path) => match formats::export(path, &self.params()) {
+                Ok(dropped) => if !dropped.is_empty() {
+                    println!("not exported: {}", dropped.join(", "))
+                }
+                Err(e) => println!("failed to export: {}", e)
+            }
+            Pyramid(kind, tile) => {
+                let r = match Renderer::new(&self.params()) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        println!("{}", e);
+                        return
+                    }
+                };
+                let done = match kind {
+                    PyramidKind::Xyz(dir, levels) => pyramid::export_xyz(&r, self.centre, self.radius, self.angle, dir, levels, tile),
+                    PyramidKind::Dzi(path) => pyramid::export_dzi(&r, path, tile),
+                };
+                if let Err(e) = done {
+                    println!("failed to export pyramid: {}", e)
+                }
+            }
+            Animate(keyframes, frames, dir) => {
+                if let Err(e) = self.animate(keyframes, frames, dir) {
+                    println!("animation failed: {}", e)
+                }
+            }
+            ExpMap(out, width, inner) => {
+                if !output::is_png(out) {
+                    println!("strips are saved as png, to keep their parameters");
+                    return
+                }
+                match expmap::strip_params(&self.params(), width, inner) {
+                    Ok(p) => {
+                        println!("strip is {}x{}", p.width, p.height);
+                        self.render(out, &p)
+                    }
+                    Err(e) => println!("{}", e)
+                }
+            }
+            ExpZoom(strip, frames, out, size) => {
+                let (w, h) = size.unwrap_or((self.iw, self.ih));
+                if let Err(e) = self.expzoom(strip, frames, out, w, h) {
+                    println!("expzoom failed: {}", e)
+                }
+            }
+            Serve(port) => {
+                if self.served.is_some() {
+                    println!("already serving");
+                    return
+                }
+                let view = Arc::new(Mutex::new(self.params()));
+                match server::serve(port, view.clone()) {
+                    Ok(()) => self.served = Some(view),
+                    Err(e) => println!("failed to serve: {}", e)
+                }
+            }
+            Share => println!("{}", self.params().to_share_string()),
+            Open(code) => match ViewParams::from_share_string(code, &self.params()) {
+                Ok(p) => {
+                    self.record();
+                    self.apply_params(&p)
+                }
+                Err(e) => println!("failed to open: {}", e)
+            }
+            Bookmark(b) => {
+                let r = Bookmarks::open().and_then(|store| match b {
+                    BookmarkCommand::Add(name) => store.add(name, &self.params()),
+                    BookmarkCommand::List => {
+                        store.list()?.iter().for_each(|n| println!("{}", n));
+                        Ok(())
+                    }
+                    BookmarkCommand::Go(name) => {
+                        let p = store.get(name)?;
+                        self.record();
+                        self.apply_params(&p);
+                        Ok(())
+                    }
+                    BookmarkCommand::Export(path) => {
+                        let names = store.export_sheet(path)?;
+                        println!("exported {} bookmarks: {}", names.len(), names.join(" "));
+                        Ok(())
+                    }
+                });
+                if let Err(e) = r {
+                    println!("bookmark failed: {}", e)
+                }
+            }
+            Recolour(data, palette, out, mapping) => {
+                let start = std::time::Instant::now();
+                match rawdata::recolour(data, palette, mapping, out, self.depth) {
+                    Ok(()) => println!("recoloured in {}ms", start.elapsed().as_millis()),
+                    Err(e) => println!("recolour failed: {}", e)
+                }
+            }
+            Set(s) => match s {
+                Setting::Iters(Some(i)) => {
+                    self.max_iter = i;
+                    self.auto_iters = false
+                }
+                Setting::Iters(None) => self.auto_iters = true,
+                Setting::Aa(aa) => self.aa = aa,
+                Setting::Depth(d) => self.depth = d,
+                Setting::Tile(rows) => self.tile_rows = rows,
+                Setting::Sampling(s) => self.sampling = s,
+                Setting::Filter(f) => self.filter = f,
+                Setting::Adaptive(t) => self.adaptive = t,
+                Setting::Palette(p) => self.palette = p,
+                Setting::PaletteOffset(o) => self.palette_offset = o,
+                Setting::Mapping(m) => self.mapping = m,
+                Setting::Projection(p) => self.projection = p,
+                Setting::Raw(r) => self.raw = r,
+                Setting::Fps(f) => self.fps = f,
+                Setting::Loop(l) => self.looping = l,
+                Setting::ColourLock(c) => self.colour_lock = c,
+                Setting::Shutter(s) => self.shutter = s,
+                Setting::TimeSamples(n) => self.time_samples = n,
+                Setting::Out(o) => {
+                    self.out = o.to_owned();
+                    self.render_count = 0;
+                }
+            }
+            Settings => {
+                println!("centre: {} {}", self.centre.real, self.centre.imag);
+                println!("radius: {}, angle: {}, projection: {}", self.radius, self.angle, self.projection.name());
+                println!("resolution: {}x{} (viewfinder {}x{})", self.iw, self.ih, self.vw, self.vh);
+                println!("formula: {}, palette: {} (offset {}), mapping: {}", self.formula.name(), self.palette.name(), self.palette_offset, self.mapping.name());
+                let iters = if self.auto_iters { String::from("auto") } else { self.max_iter.to_string() };
+                println!("iters: {}, aa: {}, out: {}, depth: {}, tile: {}", iters, self.aa, self.out, self.depth, self.tile_rows);
+                let adaptive = self.adaptive.map_or(String::from("off"), |t| t.to_string());
+                println!("sampling: {}, filter: {}, adaptive: {}, raw: {}", self.sampling.name(), self.filter.name(), adaptive, if self.raw { "on" } else { "off" });
+                println!("fps: {}, loop: {}, colourlock: {}", self.fps, if self.looping { "on" } else { "off" }, self.colour_lock.name());
+                println!("shutter: {}, timesamples: {}", self.shutter, self.time_samples);
             }
-            _ => println!("beans")
         }
 
     }
 
-    fn render(&self, name: &str, max_iter: usize, aa: usize) {
-        let i = if aa <= 1 {
-            self.render_no_aa(max_iter)
+    fn animate(&self, keyframes: &str, frames: usize, dir: &str) -> Result<(), String> {
+        let keys = animation::parse_keyframes(&std::fs::read_to_string(keyframes).map_err(|e| e.to_string())?)?;
+        if let ColourLock::Reference(f) = self.colour_lock {
+            if f >= frames {
+                return Err(format!("reference frame {} is past the last frame ({})", f, frames - 1))
+            }
+        }
+        let base = self.params();
+        let start = std::time::Instant::now();
+        // a single frame has no interval for the shutter to stay open over
+        if self.shutter > 0.0 && frames == 1 {
+            println!("motion blur needs at least two frames, rendering without it")
+        }
+        let samples = if self.shutter > 0.0 && frames > 1 { self.time_samples } else { 1 };
+        // locked or blurred frames are coloured and resolved here, not by render
+        let blended = self.colour_lock != ColourLock::Off || samples > 1;
+        if blended && self.adaptive.is_some() {
+            println!("adaptive aa isn't used with a colour lock or motion blur")
+        }
+        let mut out = if video::is_video(dir) {
+            if self.tile_rows > 0 || self.raw {
+                println!("tiling and raw data aren't used for video output")
+            }
+            Some(video::Video::create(dir, base.width, base.height, frames as u32, self.fps, self.looping)?)
         }
         else {
-            self.render_aa(max_iter, aa)
+            if blended && (self.tile_rows > 0 || self.raw) {
+                println!("tiling and raw data aren't used with a colour lock or motion blur")
+            }
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            None
         };
+        let times = animation::frame_times(&keys, frames);
+        let dt = if frames > 1 { times[1] - times[0] } else { 0.0 };
+        let mut colouring = animation::Colouring::new(self.colour_lock);
+        let reference = |n: usize| Renderer::new(&animation::view_at(&keys, times[n], &base));
+        for (n, &t) in times.iter().enumerate() {
+            println!("frame {}/{} (t = {})", n + 1, frames, t);
+            let view = animation::view_at(&keys, t, &base);
+            let name = std::path::Path::new(dir).join(format!("frame_{:05}.png", n));
+            let name = name.to_string_lossy();
+            if out.is_none() && !blended {
+                self.render(&name, &view);
+                continue
+            }
+            let rs = animation::shutter_times(t, dt, self.shutter, samples).into_iter()
+                .map(|t| Renderer::new(&animation::view_at(&keys, t, &base)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let reference_frame = match self.colour_lock {
+                ColourLock::Reference(f) => f,
+                _ => 0
+            };
+            let img = colouring.render(&rs, || reference(reference_frame))?;
+            match &mut out {
+                Some(v) => v.write_frame(&img)?,
+                None => output::save_image(&name, &img, &view, self.depth)?
+            }
+        }
+        if let Some(v) = out {
+            v.finish()?
+        }
+        println!("animation took {}ms", start.elapsed().as_millis());
+        Ok(())
+    }
 
-        if let Err(e) = i.save(name) {
-            println!("failed to save: {}", e)
+    fn expzoom(&self, strip: &str, frames: usize, out: &str, w: u32, h: u32) -> Result<(), String> {
+        let start = std::time::Instant::now();
+        let strip = expmap::Strip::open(strip)?;
+        let radii = strip.frame_radii(frames, w, h)?;
+        let mut video = if video::is_video(out) {
+            Some(video::Video::create(out, w, h, frames as u32, self.fps, self.looping)?)
         }
+        else {
+            std::fs::create_dir_all(out).map_err(|e| e.to_string())?;
+            None
+        };
+        for (n, radius) in radii.into_iter().enumerate() {
+            let img = strip.frame(radius, w, h);
+            match &mut video {
+                Some(v) => v.write_frame(&img)?,
+                None => {
+                    let name = std::path::Path::new(out).join(format!("frame_{:05}.png", n));
+                    let params = ViewParams { radius, width: w, height: h, projection: Projection::Linear, .. strip.params.clone() };
+                    output::save_image(&name.to_string_lossy(), &img, &params, self.depth)?
+                }
+            }
+        }
+        if let Some(v) = video {
+            v.finish()?
+        }
+        println!("resampled {} frames in {}ms", frames, start.elapsed().as_millis());
+        Ok(())
     }
 
-    fn render_no_aa(&self, max_iter: usize) -> RgbImage {
-        let start = Instant::now();
+    /// renders a view with the session's output settings (tiling, depth, raw data)
+    fn render(&self, name: &str, params: &ViewParams) {
+        let r = match Renderer::new(params) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("{}", e);
+                return
+            }
+        };
 
-        let (g, h) = mandelbrot::mt_generate_tables(&self.render_pm, self.iw as usize, self.ih as usize, max_iter as u16);
-        println!("tables took {}ms", start.elapsed().as_millis());
-        let tables = Instant::now();
-        let mut i = RgbImage::new(self.iw, self.ih);
-        i.pixels_mut().zip(g.iter()).for_each(|(p, i)| {
-            *p = h_palette(h.get(i as usize).copied())
-        });
-        println!("colouring took {}ms", tables.elapsed().as_millis());
-        i
+        if self.raw && (self.tile_rows > 0 || self.adaptive.is_some()) {
+            println!("raw data isn't saved for tiled or adaptive renders")
+        }
+        let saved = if self.tile_rows > 0 {
+            r.render_tiled(self.tile_rows, name, params, self.depth)
+        }
+        else if self.raw && self.adaptive.is_none() {
+            let (img, g) = r.render_samples();
+            output::save_image(name, &img, params, self.depth)
+                .and_then(|_| rawdata::save(&rawdata::raw_path(name), &g, params))
+        }
+        else {
+            output::save_image(name, &r.render(), params, self.depth)
+        };
+        if let Err(e) = saved {
+            println!("failed to save: {}", e)
+        }
     }
-    fn render_aa(&self, max_iter: usize, aa: usize) -> RgbImage {
-        let start = Instant::now();
-
-        let gw = self.iw as usize * aa;
-        let gh = self.ih as usize * aa;
-        let pm = self.render_pm.scale(aa as f32);
-        let (g, h) = mandelbrot::mt_generate_tables(&pm, gw, gh, max_iter as u16);
+}
 
-        println!("tables took {}ms", start.elapsed().as_millis());
-        let tables = Instant::now();
+/// expands the placeholders in an output name template
+/// `{n}` is the render counter, `{t}` the unix timestamp, `{re}`/`{im}` the centre and `{r}` the radius
+fn expand_template(template: &str, n: usize, centre: Complex, radius: f32) -> String {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    template
+        .replace("{n}", &n.to_string())
+        .replace("{t}", &t.to_string())
+        .replace("{re}", &centre.real.to_string())
+        .replace("{im}", &centre.imag.to_string())
+        .replace("{r}", &radius.to_string())
+}
 
-        let mut buf = crate::grid::Grid::new(aa, aa, Rgb([0u8, 0, 0]));
-        let i = RgbImage::from_fn(self.iw, self.ih, |x, y| {
-            let basex = x as usize * aa;
-            let basey = y as usize * aa;
-            buf.iter_coords_mut().for_each(|(x, y, v)| {
-                let i = g.get(basex + x, basey + y) as usize;
-                *v = h_palette(h.get(i as usize).copied());
-            });
-            average_colour(buf.iter())
-        });
-        println!("colouring took {}ms", tables.elapsed().as_millis());
-        i
+fn on_off(s: &str) -> Option<bool> {
+    match s {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None
     }
 }
 
-fn parse_line(l: &str) -> Option<Command> {
+fn parse_line(l: &str) -> Option<Command<'_>> {
     let mut i = l.split_ascii_whitespace();
     Some(match i.next()? {
         "render" => {
-            let name = i.next().unwrap_or("output.png");
-            let max_iter = i.next().map(|v| v.parse().ok()).unwrap_or(Some(100))?;
-            let aa = i.next().map(|v| v.parse().ok()).unwrap_or(Some(1))?;
-            Command::Render(name, max_iter, aa)   
+            let name = i.next().filter(|n| *n != "-"); // "-" keeps the default name
+            let max_iter = i.next().map(|v| v.parse().ok().map(Some)).unwrap_or(Some(None))?;
+            let aa = i.next().map(|v| v.parse().ok().map(Some)).unwrap_or(Some(None))?;
+            Command::Render(name, max_iter, aa)
         }
         "res" => {
             let x = i.next().and_then(|v| v.parse().ok())?;
@@ -165,11 +665,140 @@ fn parse_line(l: &str) -> Option<Command> {
             Command::Resolution(x, y, sd)
         }
         "view" => {
+            let mut kind = i.clone().next()?;
+            if ["corners", "rady", "zoom", "px"].contains(&kind) {
+                i.next();
+            }
+            else {
+                kind = "radx";
+            }
+            let mut parse = || i.next().and_then(|v| v.parse().ok());
+            let centre = Complex { real: parse()?, imag: parse()? };
+            match kind {
+                "corners" => Command::Corners(centre, Complex { real: parse()?, imag: parse()? }),
+                "radx" => {
+                    let r = parse()?;
+                    let angle = parse()?;
+                    Command::View(centre, Frame::RadX(r), Some(angle))
+                }
+                _ => {
+                    let size = parse()?;
+                    let frame = match kind {
+                        "rady" => Frame::RadY(size),
+                        "zoom" => Frame::Zoom(size),
+                        _ => Frame::PixelSize(size),
+                    };
+                    Command::View(centre, frame, parse())
+                }
+            }
+        }
+        "set" => Command::Set(match i.next()? {
+            "iters" => Setting::Iters(match i.next()? {
+                "auto" => None,
+                n => Some(n.parse().ok()?)
+            }),
+            "aa" => Setting::Aa(i.next()?.parse().ok()?),
+            "out" => Setting::Out(i.next()?),
+            "sampling" => Setting::Sampling(Sampling::from_name(i.next()?)?),
+            "filter" => Setting::Filter(Filter::from_name(i.next()?)?),
+            "adaptive" => Setting::Adaptive(match i.next()? {
+                "off" => None,
+                t => Some(t.parse().ok()?)
+            }),
+            "tile" => Setting::Tile(i.next()?.parse().ok()?),
+            "depth" => Setting::Depth(i.next()?.parse().ok().filter(|d| [8, 16].contains(d))?),
+            "palette" => Setting::Palette(Palette::from_name(i.next()?)?),
+            "offset" => Setting::PaletteOffset(i.next()?.parse().ok()?),
+            "mapping" => Setting::Mapping(Mapping::from_name(i.next()?)?),
+            "projection" => Setting::Projection(Projection::from_name(i.next()?)?),
+            "raw" => Setting::Raw(on_off(i.next()?)?),
+            "fps" => Setting::Fps(i.next()?.parse().ok().filter(|f| *f > 0)?),
+            "loop" => Setting::Loop(on_off(i.next()?)?),
+            "colourlock" => Setting::ColourLock(match i.next()? {
+                "off" => ColourLock::Off,
+                "reference" => ColourLock::Reference(i.next().map(|n| n.parse().ok()).unwrap_or(Some(0))?),
+                "fixed" => ColourLock::Fixed,
+                "smooth" => ColourLock::Smooth(i.next()?.parse().ok().filter(|n| *n > 0)?),
+                _ => return None
+            }),
+            "shutter" => Setting::Shutter(i.next()?.parse().ok().filter(|s| (0.0..=360.0).contains(s))?),
+            "timesamples" => Setting::TimeSamples(i.next()?.parse().ok().filter(|n| *n > 0)?),
+            _ => return None
+        }),
+        "zoom" => {
+            let factor = i.next()?.parse().ok().filter(|f: &f32| *f > 0.0)?;
+            let at = match i.next() {
+                Some(px) => Some((px.parse().ok()?, i.next()?.parse().ok()?)),
+                None => None
+            };
+            Command::Zoom(factor, at)
+        }
+        "pan" => Command::Pan(Offset::parse(i.next()?)?, Offset::parse(i.next()?)?),
+        "rotate" => Command::Rotate(i.next()?.parse().ok()?),
+        "goto" => {
             let real = i.next().and_then(|v| v.parse().ok())?;
             let imag = i.next().and_then(|v| v.parse().ok())?;
-            let r = i.next().and_then(|v| v.parse().ok())?;
-            let angle = i.next().and_then(|v| v.parse().ok())?;
-            Command::View(Complex { real, imag }, r, angle)
+            Command::Goto(Complex { real, imag })
+        }
+        "back" => Command::Back,
+        "forward" => Command::Forward,
+        "history" => Command::History,
+        "save" => Command::Save(i.next()?),
+        "load" => Command::Load(i.next()?),
+        "import" => Command::Import(i.next()?),
+        "export" => {
+            // a bare npy or csv is a data format, anything else is a file for another program
+            let format = match i.next()? {
+                "npy" => DataFormat::Npy,
+                "csv" => DataFormat::Csv,
+                path => return Some(Command::Export(path))
+            };
+            let path = i.next()?;
+            let size = match i.next() {
+                Some(w) => Some((w.parse().ok()?, i.next()?.parse().ok()?)),
+                None => None
+            };
+            Command::ExportData(format, path, size)
+        }
+        "pyramid" => {
+            let kind = match i.next()? {
+                "xyz" => PyramidKind::Xyz(i.next()?, i.next()?.parse().ok()?),
+                "dzi" => PyramidKind::Dzi(i.next()?),
+                _ => return None
+            };
+            let tile = i.next().map(|v| v.parse().ok()).unwrap_or(Some(256))?;
+            Command::Pyramid(kind, tile)
+        }
+        "animate" => Command::Animate(i.next()?, i.next()?.parse().ok().filter(|f| *f > 0)?, i.next()?),
+        "expmap" => Command::ExpMap(i.next()?, i.next()?.parse().ok().filter(|w| *w > 0)?, i.next()?.parse().ok()?),
+        "expzoom" => {
+            let (strip, frames, out) = (i.next()?, i.next()?.parse().ok().filter(|f| *f > 0)?, i.next()?);
+            let size = match i.next() {
+                Some(w) => Some((w.parse().ok().filter(|w| *w > 0)?, i.next()?.parse().ok().filter(|h| *h > 0)?)),
+                None => None
+            };
+            Command::ExpZoom(strip, frames, out, size)
+        }
+        "serve" => Command::Serve(match (i.next(), i.next()) {
+            (None, _) => 8000,
+            (Some("--port"), Some(p)) => p.parse().ok()?,
+            _ => return None
+        }),
+        "share" => Command::Share,
+        "open" => Command::Open(i.next()?),
+        "bookmark" => Command::Bookmark(match i.next()? {
+            "add" => BookmarkCommand::Add(i.next()?),
+            "list" => BookmarkCommand::List,
+            "go" => BookmarkCommand::Go(i.next()?),
+            "export" => BookmarkCommand::Export(i.next()?),
+            _ => return None
+        }),
+        "recolour" => {
+            let data = i.next()?;
+            let palette = Palette::from_name(i.next()?)?;
+            let out = i.next()?;
+            let mapping = i.next().map(|m| Mapping::from_name(m).map(Some)).unwrap_or(Some(None))?;
+            Command::Recolour(data, palette, out, mapping)
         }
         "settings" => Command::Settings,
         _ => return None
@@ -185,15 +814,169 @@ pub struct ViewUpdate {
 
 enum Command<'a> {
     /// renders the current view to a file
-    /// name, max iter, aa
-    Render(&'a str, usize, usize),
+    /// name, max iter, aa. anything left out falls back to the session defaults
+    Render(Option<&'a str>, Option<usize>, Option<usize>),
     /// changes the resolution of the target view and viewfinder
     /// the float is scale divisor, ie. how many pixels of render per every pixel of viewfinder
     /// if it's 3, divide the resolution by 3 and send that to the viewfinder
     Resolution(u32, u32, f32),
-    /// changes the position, radius and angle of the current view
-    View(Complex, f32, f32),
+    /// changes the position, size and angle of the current view
+    /// `view re im r angle`, or `view rady|zoom|px re im size [angle]`, leaving angle as it was if it's missing
+    View(Complex, Frame, Option<f32>),
+    /// frames the view on two opposite corners, unrotated
+    Corners(Complex, Complex),
+
+    /// divides the radius by the factor, optionally keeping the point under a render pixel fixed
+    Zoom(f32, Option<(f32, f32)>),
+    /// moves the centre by a screen offset, positive is right and down
+    Pan(Offset, Offset),
+    /// turns the view by some degrees
+    Rotate(f32),
+    /// moves the centre to a point, keeping radius and angle
+    Goto(Complex),
+
+    /// steps back through view history
+    Back,
+    /// re-does a view change undone by back
+    Forward,
+    /// prints the view history, with the current view marked
+    History,
+
+    /// writes the current view parameters to a file
+    Save(&'a str),
+    /// reads view parameters back from a file written by save, or from a png written by render
+    Load(&'a str),
+
+    /// reads a .kfr, .par, .xpf or .upr file from another fractal program
+    Import(&'a str),
+    /// writes the view for another fractal program, picked by extension
+    Export(&'a str),
+    /// writes iteration counts, smooth counts and |z| for analysis, at the given size or the render resolution
+    /// `export npy|csv <file> [w h]`
+    ExportData(DataFormat, &'a str, Option<(u32, u32)>),
+
+    /// exports a zoomable tile pyramid with the given tile size, using the session iters, aa and filter
+    Pyramid(PyramidKind<'a>, usize),
+
+    /// renders a keyframe file into a numbered png sequence in a directory, or one .y4m, .gif or .apng file
+    /// `animate <keyframes> <frames> <dir|file>`, see animation.rs for the keyframe format
+    Animate(&'a str, usize, &'a str),
+
+    /// renders an exponential map strip around the centre, from the corners of the view down to a radius
+    /// `expmap <out.png> <width> <inner radius>`
+    ExpMap(&'a str, u32, f32),
+    /// resamples an expmap strip into zoom frames, written like animate's
+    /// `expzoom <strip.png> <frames> <dir|file> [w h]`, at the render resolution by default
+    ExpZoom(&'a str, usize, &'a str, Option<(u32, u32)>),
+
+    /// starts a tile server on localhost, `serve [--port N]`
+    Serve(u16),
+
+    /// prints the view as a one-line string for pasting elsewhere
+    Share,
+    /// goes to a view from a share string
+    Open(&'a str),
+
+    /// manages the per-user bookmark store
+    Bookmark(BookmarkCommand<'a>),
+
+    /// colours raw data saved by render with a palette (and optionally a mapping) into an image
+    /// `recolour <data> <palette> <out> [mapping]`
+    Recolour(&'a str, Palette, &'a str, Option<Mapping>),
+
+    /// changes one of the session defaults used by render
+    Set(Setting<'a>),
 
     /// prints the current view information to the console
     Settings
 }
+
+enum Offset {
+    /// fraction of the screen size
+    Fraction(f32),
+    /// render pixels, written with a px suffix
+    Pixels(f32),
+}
+impl Offset {
+    fn parse(s: &str) -> Option<Self> {
+        match s.strip_suffix("px") {
+            Some(px) => px.parse().ok().map(Offset::Pixels),
+            None => s.parse().ok().map(Offset::Fraction)
+        }
+    }
+    fn to_pixels(&self, size: u32) -> f32 {
+        match *self {
+            Offset::Fraction(f) => f * size as f32,
+            Offset::Pixels(p) => p,
+        }
+    }
+}
+
+enum PyramidKind<'a> {
+    /// directory and number of levels
+    Xyz(&'a str, u32),
+    /// .dzi file, sized from the current resolution
+    Dzi(&'a str),
+}
+
+enum BookmarkCommand<'a> {
+    /// saves the current view and a thumbnail under a name
+    Add(&'a str),
+    List,
+    /// jumps to a saved view
+    Go(&'a str),
+    /// writes every thumbnail into one grid image
+    Export(&'a str),
+}
+
+enum Setting<'a> {
+    /// default max iterations, or none to pick them per render
+    Iters(Option<usize>),
+    /// default antialiasing factor
+    Aa(usize),
+    /// output name template, see `expand_template`
+    Out(&'a str),
+    /// where antialiasing samples go in each pixel
+    Sampling(Sampling),
+    /// how samples are weighted into pixels
+    Filter(Filter),
+    /// colour difference (0 to 1) past which a pixel gets antialiased, or none to antialias everything
+    /// aa becomes the cap on samples per axis
+    Adaptive(Option<f32>),
+    /// rows per strip for tiled, streamed png rendering, 0 for off
+    Tile(usize),
+    /// 8 or 16 bit png output. tiff is always 16 bit, exr and pfm are float
+    Depth(u8),
+    Palette(Palette),
+    /// shift along the palette, reflecting at its ends
+    PaletteOffset(f32),
+    /// how iteration counts are spread over the palette
+    Mapping(Mapping),
+    /// linear or expmap, for renders
+    Projection(Projection),
+    /// also write the raw samples of each render to a .fwraw file beside it
+    Raw(bool),
+    /// frame rate of y4m, gif and apng animations
+    Fps(u32),
+    /// whether gif and apng animations repeat forever or play once
+    Loop(bool),
+    /// how animation frames share their colouring
+    /// `set colourlock off|reference [frame]|fixed|smooth N`
+    ColourLock(ColourLock),
+    /// shutter angle for motion blur in animations, 0 to 360 degrees of the frame interval
+    Shutter(f32),
+    /// sub-frames per animation frame while the shutter is open
+    TimeSamples(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn template_expansion() {
+        let c = Complex { real: -0.5, imag: 0.25 };
+        assert_eq!(expand_template("renders/{n}.png", 3, c, 1.0), "renders/3.png");
+        assert_eq!(expand_template("{re}_{im}_{r}.png", 0, c, 0.125), "-0.5_0.25_0.125.png");
+        assert_eq!(expand_template("plain.png", 7, c, 1.0), "plain.png");
+    }
+}
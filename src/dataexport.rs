@@ -0,0 +1,126 @@
+// iteration data for analysis elsewhere: numpy .npy arrays or a long format csv
+// every pixel is sampled once at its centre. points that didn't escape have iter = max_iter and
+// nan smooth and |z|
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::utils::*;
+use crate::grid::Grid;
+use crate::mandelbrot;
+use crate::params::ViewParams;
+use crate::pixelmapper::PixelMapper;
+
+#[derive(Debug, Clone, Copy)]
+pub enum DataFormat {
+    Npy,
+    Csv,
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    c: Complex,
+    iter: u32,
+    smooth: f32,
+    absz: f32,
+}
+
+fn sample(pm: &PixelMapper, width: usize, height: usize, max_iter: usize) -> Grid<Sample> {
+    let blank = Sample { c: Complex::ZERO, iter: 0, smooth: 0.0, absz: 0.0 };
+    let mut g = Grid::new(width, height, blank);
+    g.par_iter_rows_mut().for_each(|(y, row)| {
+        row.iter_mut().enumerate().for_each(|(x, s)| {
+            let c = pm.map_centre(x, y);
+            let (i, z) = mandelbrot::do_point_escape(c, max_iter);
+            *s = Sample {
+                c,
+                iter: i as u32,
+                smooth: z.map_or(f32::NAN, |z| mandelbrot::smooth_count(i, z)),
+                absz: z.map_or(f32::NAN, |z| z.magnitude()),
+            }
+        })
+    });
+    g
+}
+
+/// exports the view at width x height. npy writes path_iter.npy, _smooth, _absz, _re and _im
+/// (with any .npy extension taken off path first) plus path.json describing the pixel to plane mapping
+/// csv writes one line per pixel
+pub fn export(format: DataFormat, path: &str, p: &ViewParams, width: u32, height: u32) -> Result<Vec<String>, String> {
+    if width == 0 || height == 0 {
+        return Err(String::from("size must be at least 1x1"))
+    }
+    let max_iter = u32::try_from(p.max_iter).map_err(|_| "max_iter too large")?;
+    let pm = PixelMapper::new_radx(p.centre, p.radius, p.angle, width, height);
+    let g = sample(&pm, width as usize, height as usize, max_iter as usize);
+    match format {
+        DataFormat::Npy => {
+            let base = path.strip_suffix(".npy").unwrap_or(path);
+            let shape = (height as usize, width as usize);
+            let mut written = Vec::new();
+            let mut array = |name: &str, descr: &str, value: &dyn Fn(&Sample) -> [u8; 4]| {
+                let f = format!("{}_{}.npy", base, name);
+                let data: Vec<u8> = g.iter().flat_map(|s| value(&s)).collect();
+                write_npy(&f, descr, shape, &data)?;
+                written.push(f);
+                Ok::<(), String>(())
+            };
+            array("iter", "<u4", &|s| s.iter.to_le_bytes())?;
+            array("smooth", "<f4", &|s| s.smooth.to_le_bytes())?;
+            array("absz", "<f4", &|s| s.absz.to_le_bytes())?;
+            array("re", "<f4", &|s| s.c.real.to_le_bytes())?;
+            array("im", "<f4", &|s| s.c.imag.to_le_bytes())?;
+
+            let f = format!("{}.json", base);
+            std::fs::write(&f, sidecar(&pm, p, width, height, max_iter)).map_err(|e| e.to_string())?;
+            written.push(f);
+            Ok(written)
+        }
+        DataFormat::Csv => {
+            let f = std::fs::File::create(path).map_err(|e| e.to_string())?;
+            let mut w = BufWriter::new(f);
+            let mut write = || -> std::io::Result<()> {
+                writeln!(w, "x,y,re,im,iter,smooth,absz")?;
+                for (x, y, s) in g.iter_coords() {
+                    writeln!(w, "{},{},{},{},{},{},{}", x, y, s.c.real, s.c.imag, s.iter, s.smooth, s.absz)?;
+                }
+                w.flush()
+            };
+            write().map_err(|e| e.to_string())?;
+            Ok(vec![path.to_owned()])
+        }
+    }
+}
+
+/// the point for pixel (x, y) is origin + x * dx + y * dy, as [re, im] pairs
+fn sidecar(pm: &PixelMapper, p: &ViewParams, width: u32, height: u32, max_iter: u32) -> String {
+    let origin = pm.map_centre(0, 0);
+    let dx = pm.map(1, 0) - pm.map(0, 0);
+    let dy = pm.map(0, 1) - pm.map(0, 0);
+    let pair = |c: Complex| format!("[{}, {}]", c.real, c.imag);
+    format!(
+        "{{\n  \"width\": {},\n  \"height\": {},\n  \"max_iter\": {},\n  \"origin\": {},\n  \"dx\": {},\n  \"dy\": {},\n  \"share\": \"{}\"\n}}\n",
+        width, height, max_iter, pair(origin), pair(dx), pair(dy), p.to_share_string()
+    )
+}
+
+/// a version 1.0 .npy file holding a c order array
+fn write_npy(path: &str, descr: &str, shape: (usize, usize), data: &[u8]) -> Result<(), String> {
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}", descr, shape.0, shape.1);
+    // magic, version and length take 10 bytes, and the data should start 64 byte aligned
+    let total = (10 + header.len() + 1).div_ceil(64) * 64;
+    header.extend(std::iter::repeat_n(' ', total - 10 - header.len() - 1));
+    header.push('\n');
+
+    let mut f = BufWriter::new(std::fs::File::create(Path::new(path)).map_err(|e| e.to_string())?);
+    let mut write = || -> std::io::Result<()> {
+        f.write_all(b"\x93NUMPY\x01\x00")?;
+        f.write_all(&(header.len() as u16).to_le_bytes())?;
+        f.write_all(header.as_bytes())?;
+        f.write_all(data)?;
+        f.flush()
+    };
+    write().map_err(|e| e.to_string())
+}
@@ -0,0 +1,93 @@
+// exponential map zooms
+// one log-polar strip around the centre holds every radius from the corners of the outermost frame
+// down to the deepest, so a whole zoom renders once and its frames are resampled out of the strip
+
+use image::{Rgb, Rgb32FImage};
+use rayon::prelude::*;
+
+use crate::utils::*;
+use crate::params::ViewParams;
+use crate::pixelmapper::{PixelMapper, Projection};
+use crate::output;
+
+/// parameters for a strip width pixels round, from the corners of the view in p down to radius inner
+pub fn strip_params(p: &ViewParams, width: u32, inner: f32) -> Result<ViewParams, String> {
+    let outer = p.radius * (1.0 + (p.height as f32 / p.width as f32).powi(2)).sqrt();
+    if !(inner > 0.0 && inner < outer) {
+        return Err(format!("the inner radius should be between 0 and {}", outer))
+    }
+    Ok(ViewParams {
+        radius: outer,
+        width,
+        height: PixelMapper::expmap_rows(outer, inner, width),
+        projection: Projection::ExpMap,
+        .. p.clone()
+    })
+}
+
+pub struct Strip {
+    /// linear light, so resampling blends properly
+    img: Rgb32FImage,
+    pub params: ViewParams,
+    pm: PixelMapper,
+}
+impl Strip {
+    /// reads a strip png written by render, with its parameters
+    pub fn open(path: &str) -> Result<Self, String> {
+        let params = output::load_png_params(path)?;
+        if params.projection != Projection::ExpMap {
+            return Err(format!("{} isn't an exponential map strip", path))
+        }
+        let mut img = image::open(path).map_err(|e| e.to_string())?.to_rgb32f();
+        if (img.width(), img.height()) != (params.width, params.height) {
+            return Err(String::from("strip size doesn't match its parameters"))
+        }
+        img.pixels_mut().for_each(|p| p.0 = p.0.map(srgb_to_linear));
+        let pm = PixelMapper::new_expmap(params.centre, params.radius, params.angle, params.width);
+        Ok(Self { img, params, pm })
+    }
+
+    /// half widths of frames width x height, evenly spaced in log from the frame whose corners touch
+    /// the outside of the strip in to the one where the strip's innermost row is a pixel from the centre
+    pub fn frame_radii(&self, frames: usize, width: u32, height: u32) -> Result<Vec<f32>, String> {
+        let first = self.params.radius / (1.0 + (height as f32 / width as f32).powi(2)).sqrt();
+        let inner = (self.pm.map_f(0.0, self.params.height as f32) - self.params.centre).magnitude();
+        let last = inner * width as f32 / 2.0;
+        if last >= first {
+            return Err(String::from("the strip isn't deep enough for frames that size"))
+        }
+        Ok((0..frames).map(|n| {
+            let t = if frames == 1 { 0.0 } else { n as f32 / (frames - 1) as f32 };
+            first * (last / first).powf(t)
+        }).collect())
+    }
+
+    /// an ordinary frame, bilinearly sampled from the strip
+    /// pixels closer to the centre than the strip reaches take its innermost row
+    pub fn frame(&self, radius: f32, width: u32, height: u32) -> Rgb32FImage {
+        let p = &self.params;
+        let pm = PixelMapper::new_radx(p.centre, radius, p.angle, width, height);
+        let (sw, sh) = (self.img.width() as usize, self.img.height() as usize);
+        let step = std::f32::consts::TAU / sw as f32;
+        let sample = |sx: usize, sy: usize| self.img.get_pixel(sx as u32, sy as u32).0;
+
+        let px: Vec<Rgb<f32>> = (0..(width * height) as usize).into_par_iter().map(|i| {
+            let (x, y) = (i % width as usize, i / width as usize);
+            let z = pm.map_centre(x, y) - p.centre;
+            // strip pixel centres are half a pixel in
+            let u = ((z.imag.atan2(z.real) - p.angle) / step - 0.5).rem_euclid(sw as f32);
+            let v = ((p.radius.ln() - z.magnitude().ln()) / step - 0.5).clamp(0.0, (sh - 1) as f32);
+            let (x0, y0) = (u.floor() as usize % sw, v.floor() as usize);
+            let (x1, y1) = ((x0 + 1) % sw, (y0 + 1).min(sh - 1));
+            let (fx, fy) = (u.fract(), v.fract());
+
+            let mut acc = Accum::default();
+            acc.add(sample(x0, y0), (1.0 - fx) * (1.0 - fy));
+            acc.add(sample(x1, y0), fx * (1.0 - fy));
+            acc.add(sample(x0, y1), (1.0 - fx) * fy);
+            acc.add(sample(x1, y1), fx * fy);
+            acc.resolve()
+        }).collect();
+        Rgb32FImage::from_fn(width, height, |x, y| px[(x + y * width) as usize])
+    }
+}
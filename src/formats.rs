@@ -0,0 +1,285 @@
+// parameter files from other fractal programs
+// kalles fraktaler (.kfr), fractint (.par), xaos (.xpf) and ultra fractal (.upr)
+// only the location, iterations and formula carry over. importers return the fields they couldn't map,
+// exporters the parameters the format has no place for
+
+use crate::utils::*;
+use crate::mandelbrot::Formula;
+use crate::params::ViewParams;
+use crate::pixelmapper::Frame;
+use crate::output::has_extension;
+
+/// the imported view and the names of any fields that couldn't be mapped
+type Imported = Result<(ViewParams, Vec<String>), String>;
+
+/// kalles fraktaler and ultra fractal zooms are relative to a view 2 tall either side of the centre
+const UNIT_ZOOM_RADY: f32 = 2.0;
+
+pub fn import(path: &str, base: &ViewParams) -> Imported {
+    let s = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if has_extension(path, "kfr") {
+        import_kfr(&s, base)
+    }
+    else if has_extension(path, "par") {
+        import_par(&s, base)
+    }
+    else if has_extension(path, "xpf") {
+        import_xpf(&s, base)
+    }
+    else if has_extension(path, "upr") {
+        import_upr(&s, base)
+    }
+    else {
+        Err(format!("don't know how to import {}", path))
+    }
+}
+pub fn export(path: &str, p: &ViewParams) -> Result<Vec<String>, String> {
+    let (s, dropped) = if has_extension(path, "kfr") {
+        export_kfr(p)
+    }
+    else if has_extension(path, "par") {
+        export_par(p)
+    }
+    else if has_extension(path, "xpf") {
+        export_xpf(p)
+    }
+    else if has_extension(path, "upr") {
+        export_upr(p)
+    }
+    else {
+        return Err(format!("don't know how to export {}", path))
+    };
+    std::fs::write(path, s).map_err(|e| e.to_string())?;
+    Ok(dropped)
+}
+
+/// parses as f64 first, since these formats often write more digits than f32 holds
+/// values past f32's range are rejected rather than turned into infinities
+fn float(s: &str) -> Option<f32> {
+    s.trim().parse::<f64>().ok().map(|f| f as f32).filter(|f| f.is_finite())
+}
+fn complex(re: &str, im: &str) -> Option<Complex> {
+    Some(Complex { real: float(re)?, imag: float(im)? })
+}
+fn bad(k: &str, v: &str) -> String {
+    format!("bad value for {}: {}", k, v)
+}
+/// the importers' result, as long as the view has a size f32 can render
+fn checked(p: ViewParams, unmapped: Vec<String>) -> Imported {
+    if !(p.radius.is_finite() && p.radius > 0.0) {
+        return Err(format!("view size is out of range (radius {})", p.radius))
+    }
+    Ok((p, unmapped))
+}
+
+fn import_kfr(s: &str, base: &ViewParams) -> Imported {
+    let mut p = base.clone();
+    let mut unmapped = Vec::new();
+    let (mut re, mut im, mut zoom) = (None, None, None);
+    for l in s.lines() {
+        let Some((k, v)) = l.split_once(':') else { continue };
+        let (k, v) = (k.trim(), v.trim());
+        match k {
+            "Re" => re = Some(float(v).ok_or_else(|| bad(k, v))?),
+            "Im" => im = Some(float(v).ok_or_else(|| bad(k, v))?),
+            "Zoom" => zoom = Some(float(v).ok_or_else(|| bad(k, v))?),
+            "Iterations" => p.max_iter = v.parse().map_err(|_| bad(k, v))?,
+            "Rotate" => p.angle = float(v).ok_or_else(|| bad(k, v))?.to_radians(),
+            "FractalType" if v == "0" => {}
+            "Power" if v == "2" => {}
+            _ => unmapped.push(k.to_owned())
+        }
+    }
+    p.centre = Complex { real: re.ok_or("missing Re")?, imag: im.ok_or("missing Im")? };
+    p.radius = Frame::RadY(UNIT_ZOOM_RADY / zoom.ok_or("missing Zoom")?).radx(p.width, p.height);
+    p.formula = Formula::Mandelbrot;
+    checked(p, unmapped)
+}
+fn export_kfr(p: &ViewParams) -> (String, Vec<String>) {
+    let rady = p.radius * p.height as f32 / p.width as f32;
+    let s = format!(
+        "Re: {}\r\nIm: {}\r\nZoom: {}\r\nIterations: {}\r\nRotate: {}\r\nFractalType: 0\r\nPower: 2\r\n",
+        p.centre.real, p.centre.imag, UNIT_ZOOM_RADY / rady, p.max_iter, p.angle.to_degrees()
+    );
+    (s, vec![String::from("palette"), String::from("aa"), String::from("resolution")])
+}
+
+/// the body of the first `name { ... }` entry, with ; comments removed
+fn first_entry(s: &str) -> Result<String, String> {
+    let s: String = s.lines().map(|l| l.split(';').next().unwrap_or("")).collect::<Vec<_>>().join("\n");
+    let start = s.find('{').ok_or("no entry found")?;
+    let end = s[start..].find('}').ok_or("unterminated entry")?;
+    Ok(s[start + 1..start + end].to_owned())
+}
+
+fn import_par(s: &str, base: &ViewParams) -> Imported {
+    let mut p = base.clone();
+    let mut unmapped = Vec::new();
+    let mut located = false;
+    for t in first_entry(s)?.split_ascii_whitespace() {
+        let Some((k, v)) = t.split_once('=') else { continue };
+        let nums: Vec<_> = v.split('/').collect();
+        match k {
+            "type" if v == "mandel" => p.formula = Formula::Mandelbrot,
+            "maxiter" => p.max_iter = v.parse().map_err(|_| bad(k, v))?,
+            "corners" if nums.len() == 4 => {
+                let a = complex(nums[0], nums[2]).ok_or_else(|| bad(k, v))?;
+                let b = complex(nums[1], nums[3]).ok_or_else(|| bad(k, v))?;
+                (p.centre, p.radius) = crate::pixelmapper::corners_view(a, b, p.width, p.height);
+                p.angle = 0.0;
+                located = true;
+            }
+            // centre x/y/mag[/xmagfactor/rotation/skew], mag 1 is 1 either side vertically
+            "center-mag" if nums.len() >= 3 => {
+                p.centre = complex(nums[0], nums[1]).ok_or_else(|| bad(k, v))?;
+                let mag = float(nums[2]).ok_or_else(|| bad(k, v))?;
+                let xmag = nums.get(3).and_then(|v| float(v)).unwrap_or(1.0);
+                p.radius = Frame::RadY(1.0 / mag).radx(p.width, p.height) / xmag;
+                p.angle = nums.get(4).and_then(|v| float(v)).unwrap_or(0.0).to_radians();
+                if nums.get(5).and_then(|v| float(v)).is_some_and(|skew| skew != 0.0) {
+                    unmapped.push(String::from("center-mag skew"))
+                }
+                located = true;
+            }
+            "reset" => {}
+            _ => unmapped.push(k.to_owned())
+        }
+    }
+    if !located {
+        return Err(String::from("entry has no corners or center-mag"))
+    }
+    checked(p, unmapped)
+}
+fn export_par(p: &ViewParams) -> (String, Vec<String>) {
+    let rady = p.radius * p.height as f32 / p.width as f32;
+    let s = format!(
+        "fractal_window {{\n  reset=2004 type=mandel\n  center-mag={}/{}/{}/1/{}\n  maxiter={}\n  }}\n",
+        p.centre.real, p.centre.imag, 1.0 / rady, p.angle.to_degrees(), p.max_iter
+    );
+    (s, vec![String::from("palette"), String::from("aa"), String::from("resolution")])
+}
+
+fn import_xpf(s: &str, base: &ViewParams) -> Imported {
+    let mut p = base.clone();
+    let mut unmapped = Vec::new();
+    let mut located = false;
+    let body: String = s.lines().map(|l| l.split(';').next().unwrap_or("")).collect::<Vec<_>>().join(" ");
+    for form in body.split('(').skip(1) {
+        let form = form.split(')').next().unwrap_or("");
+        let mut i = form.split_ascii_whitespace();
+        let Some(k) = i.next() else { continue };
+        let args: Vec<_> = i.collect();
+        match (k, args.as_slice()) {
+            ("formula", ["'mandel"]) => p.formula = Formula::Mandelbrot,
+            // centre x y, then the full width and height
+            ("view", [x, y, w, _h]) => {
+                p.centre = complex(x, y).ok_or_else(|| bad(k, form))?;
+                p.radius = float(w).ok_or_else(|| bad(k, form))? / 2.0;
+                located = true;
+            }
+            ("maxiter", [n]) => p.max_iter = n.parse().map_err(|_| bad(k, form))?,
+            ("angle", [a]) => p.angle = float(a).ok_or_else(|| bad(k, form))?.to_radians(),
+            ("initstate", []) => {}
+            _ => unmapped.push(k.to_owned())
+        }
+    }
+    if !located {
+        return Err(String::from("no view in file"))
+    }
+    checked(p, unmapped)
+}
+fn export_xpf(p: &ViewParams) -> (String, Vec<String>) {
+    let w = p.radius * 2.0;
+    let h = w * p.height as f32 / p.width as f32;
+    let s = format!(
+        ";Position file written by fractal_window\n(initstate)\n(formula 'mandel)\n(view {} {} {} {})\n(maxiter {})\n(angle {})\n",
+        p.centre.real, p.centre.imag, w, h, p.max_iter, p.angle.to_degrees()
+    );
+    (s, vec![String::from("palette"), String::from("aa")])
+}
+
+fn import_upr(s: &str, base: &ViewParams) -> Imported {
+    let mut p = base.clone();
+    let mut unmapped = Vec::new();
+    let (mut centre, mut magn) = (None, None);
+    for t in first_entry(s)?.split_ascii_whitespace() {
+        // section headers like location: aren't parameters
+        let Some((k, v)) = t.split_once('=') else { continue };
+        let v = v.trim_matches('"');
+        match k {
+            "center" => {
+                let (re, im) = v.split_once('/').ok_or_else(|| bad(k, v))?;
+                centre = Some(complex(re, im).ok_or_else(|| bad(k, v))?);
+            }
+            "magn" => magn = Some(float(v).ok_or_else(|| bad(k, v))?),
+            "angle" => p.angle = float(v).ok_or_else(|| bad(k, v))?.to_radians(),
+            "maxiter" => p.max_iter = v.parse().map_err(|_| bad(k, v))?,
+            "width" => p.width = v.parse().map_err(|_| bad(k, v))?,
+            "height" => p.height = v.parse().map_err(|_| bad(k, v))?,
+            "entry" if v == "Mandelbrot" => p.formula = Formula::Mandelbrot,
+            "title" | "layers" | "filename" => {}
+            _ => unmapped.push(k.to_owned())
+        }
+    }
+    p.centre = centre.ok_or("missing center")?;
+    p.radius = Frame::RadY(UNIT_ZOOM_RADY / magn.ok_or("missing magn")?).radx(p.width, p.height);
+    checked(p, unmapped)
+}
+fn export_upr(p: &ViewParams) -> (String, Vec<String>) {
+    let rady = p.radius * p.height as f32 / p.width as f32;
+    let s = format!(
+        "fractal_window {{\nfractal:\n  title=\"fractal_window\" width={} height={} layers=1\nlocation:\n  center={}/{} magn={} angle={}\nformula:\n  maxiter={} filename=\"Standard.ulb\" entry=\"Mandelbrot\"\n}}\n",
+        p.width, p.height, p.centre.real, p.centre.imag, UNIT_ZOOM_RADY / rady, p.angle.to_degrees(), p.max_iter
+    );
+    (s, vec![String::from("palette"), String::from("aa")])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= a.abs().max(b.abs()) * 1e-5
+    }
+    fn check_round_trip(export: fn(&ViewParams) -> (String, Vec<String>), import: fn(&str, &ViewParams) -> Imported) {
+        let p = ViewParams {
+            centre: Complex { real: -0.75, imag: 0.125 },
+            radius: 0.01,
+            angle: 0.5,
+            max_iter: 2500,
+            .. ViewParams::default()
+        };
+        let (s, _) = export(&p);
+        let (q, unmapped) = import(&s, &ViewParams::default()).unwrap();
+        assert!(unmapped.is_empty(), "{:?}", unmapped);
+        assert_eq!(q.centre, p.centre);
+        assert!(close(q.radius, p.radius), "{} {}", q.radius, p.radius);
+        assert!(close(q.angle, p.angle), "{} {}", q.angle, p.angle);
+        assert_eq!(q.max_iter, p.max_iter);
+    }
+    #[test]
+    fn round_trips() {
+        check_round_trip(export_kfr, import_kfr);
+        check_round_trip(export_par, import_par);
+        check_round_trip(export_xpf, import_xpf);
+        check_round_trip(export_upr, import_upr);
+    }
+    #[test]
+    fn reports_unmapped() {
+        let (_, unmapped) = import_par("x { type=julia center-mag=0/0/1 inside=0 }", &ViewParams::default()).unwrap();
+        assert_eq!(unmapped, ["type", "inside"]);
+    }
+    #[test]
+    fn tells_bad_from_missing() {
+        let base = ViewParams::default();
+        assert_eq!(import_kfr("Re: x\r\nIm: 0\r\nZoom: 1\r\n", &base).unwrap_err(), "bad value for Re: x");
+        assert_eq!(import_kfr("Im: 0\r\nZoom: 1\r\n", &base).unwrap_err(), "missing Re");
+    }
+    #[test]
+    fn rejects_out_of_range_views() {
+        let base = ViewParams::default();
+        assert_eq!(import_kfr("Re: 0\r\nIm: 0\r\nZoom: 1E50\r\n", &base).unwrap_err(), "bad value for Zoom: 1E50");
+        assert!(import_kfr("Re: 0\r\nIm: 0\r\nZoom: 1E-40\r\n", &base).unwrap_err().starts_with("view size is out of range"));
+        assert!(import_upr("x { center=0/0 magn=-2 }", &base).is_err());
+    }
+}
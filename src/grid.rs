@@ -10,6 +10,17 @@ impl<T: Copy> Grid<T> {
         v.resize(width * height, init);
         Grid { data: v, width }
     }
+    /// data is left-right then top-bottom, as iter() returns it
+    pub fn from_vec(data: Vec<T>, width: usize) -> Grid<T> {
+        assert!(width > 0 && data.len().is_multiple_of(width));
+        Grid { data, width }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.data.len() / self.width
+    }
     pub fn get(&self, x: usize, y: usize) -> T {
         self.data[x + (y * self.width)]
     }
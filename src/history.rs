@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+
+/// bounded undo/redo stack. the current state is owned by the caller
+pub struct History<T> {
+    past: VecDeque<T>,
+    future: Vec<T>,
+    cap: usize,
+}
+impl<T> History<T> {
+    pub fn new(cap: usize) -> Self {
+        Self { past: VecDeque::new(), future: Vec::new(), cap }
+    }
+
+    /// call with the state that's about to be replaced. drops anything that was undone
+    pub fn push(&mut self, current: T) {
+        self.future.clear();
+        self.past.push_back(current);
+        if self.past.len() > self.cap {
+            self.past.pop_front();
+        }
+    }
+    /// returns the state to go back to, if there is one
+    pub fn back(&mut self, current: T) -> Option<T> {
+        let prev = self.past.pop_back()?;
+        self.future.push(current);
+        Some(prev)
+    }
+    pub fn forward(&mut self, current: T) -> Option<T> {
+        let next = self.future.pop()?;
+        self.past.push_back(current);
+        Some(next)
+    }
+
+    /// oldest first
+    pub fn past(&self) -> impl Iterator<Item = &T> {
+        self.past.iter()
+    }
+    /// next first
+    pub fn future(&self) -> impl Iterator<Item = &T> {
+        self.future.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn back_and_forward() {
+        let mut h = History::new(2);
+        h.push(1);
+        h.push(2);
+        h.push(3); // 1 falls off
+        assert_eq!(h.back(4), Some(3));
+        assert_eq!(h.back(3), Some(2));
+        assert_eq!(h.back(2), None);
+        assert_eq!(h.forward(2), Some(3));
+        assert_eq!(h.forward(3), Some(4));
+        assert_eq!(h.forward(4), None);
+
+        h.back(4);
+        h.push(5); // new branch drops the redo
+        assert_eq!(h.forward(5), None);
+    }
+}
@@ -3,13 +3,27 @@ mod mandelbrot;
 mod pixelmapper;
 mod grid;
 mod control;
+mod params;
+mod output;
+mod bookmarks;
+mod history;
+mod formats;
+mod render;
+mod pyramid;
+mod server;
+mod rawdata;
+mod dataexport;
+mod animation;
+mod video;
+mod expmap;
 
-use winit::event::{Event, WindowEvent};
+use winit::event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder};
 use winit::window::WindowBuilder;
 use winit::dpi::LogicalSize;
 
 use std::num::NonZeroU32;
+use std::sync::mpsc;
 use std::thread;
 
 use pixelmapper::PixelMapper;
@@ -18,19 +32,27 @@ use utils::*;
 const STARTING_WIDTH: u32 = 1920;
 const STARTING_HEIGHT: u32 = 1080;
 
+const STARTING_CENTRE: Complex = Complex { real: -1.0, imag: 0.0 };
+/// zoom magnifications are relative to this
+const STARTING_RADIUS: f32 = 1.0;
+const STARTING_ANGLE: f32 = 1.0;
+
 const STARTING_WINDOW_WIDTH: u32 = 640;
 const STARTING_WINDOW_HEIGHT: u32 = 360;
 const STARTING_WINDOW_SIZE: LogicalSize<u32> = LogicalSize::new(STARTING_WINDOW_WIDTH, STARTING_WINDOW_HEIGHT);
 
 fn main() {
     if let Some(_) = std::env::args().nth(1) {
-        control::control_loop(None);
+        let (tx, rx) = mpsc::channel();
+        control::control_loop(None, tx, rx);
         return
     }
 
     let event_loop: EventLoop<control::ViewUpdate> = EventLoopBuilder::with_user_event().build();
     let proxy = event_loop.create_proxy();
-    thread::spawn(move || control::control_loop(Some(proxy)));
+    let (tx, rx) = mpsc::channel();
+    let keys = tx.clone();
+    thread::spawn(move || control::control_loop(Some(proxy), tx, rx));
 
     let window = WindowBuilder::new()
         .with_title("fractals!")
@@ -46,7 +68,7 @@ fn main() {
         )
         .unwrap();
 
-    let mut pm = PixelMapper::new_radx(Complex { real: -1.0, imag: 0.0 }, 1.0, 1.0, STARTING_WINDOW_WIDTH, STARTING_WINDOW_HEIGHT);
+    let mut pm = PixelMapper::new_radx(STARTING_CENTRE, STARTING_RADIUS, STARTING_ANGLE, STARTING_WINDOW_WIDTH, STARTING_WINDOW_HEIGHT);
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
@@ -77,6 +99,20 @@ fn main() {
                 buffer.present().unwrap();
             }
 
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+                    ..
+                },
+                window_id,
+            } if window_id == window.id() => {
+                let command = match key {
+                    VirtualKeyCode::LBracket => "back",
+                    VirtualKeyCode::RBracket => "forward",
+                    _ => return
+                };
+                let _ = keys.send(command.to_owned());
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 window_id,
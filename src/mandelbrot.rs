@@ -3,6 +3,25 @@ use crate::pixelmapper::PixelMapper;
 use crate::grid::Grid;
 use rayon::prelude::*;
 
+/// the iteration being rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Formula {
+    Mandelbrot,
+}
+impl Formula {
+    pub fn name(self) -> &'static str {
+        match self {
+            Formula::Mandelbrot => "mandelbrot",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "mandelbrot" => Some(Formula::Mandelbrot),
+            _ => None
+        }
+    }
+}
+
 fn iter(z: Complex, c: Complex) -> Complex {
     z.square() + c
 }
@@ -35,13 +54,19 @@ pub fn do_point(c: Complex, max_iter: usize) -> Option<usize> {
 }
 /// return will be >= max_iter if the point didn't escape
 pub fn do_point_optimised(c: Complex, max_iter: usize) -> usize {
+    do_point_escape(c, max_iter).0
+}
+
+/// as do_point_optimised, also returning z from the iteration it escaped on
+/// z is none if the point didn't escape
+pub fn do_point_escape(c: Complex, max_iter: usize) -> (usize, Option<Complex>) {
     // cardioid/bulb checking
     let p = ((c.real - 0.25).powi(2) + c.imag.powi(2)).sqrt();
     if c.real <= p - (2.0 * p.powi(2)) + 0.25 {
-        return max_iter
+        return (max_iter, None)
     }
     if (c.real + 1.0).powi(2) + c.imag.powi(2) <= 1.0 / 16.0 {
-        return max_iter
+        return (max_iter, None)
     }
 
     let mut x = c.real;
@@ -63,29 +88,42 @@ pub fn do_point_optimised(c: Complex, max_iter: usize) -> usize {
         y2 = y.powi(2);
 
         if float_fuzzy_eq(x, oldx) && float_fuzzy_eq(y, oldy) {
-            return max_iter
+            return (max_iter, None)
         }
 
         if x2 + y2 > 4.0 {
-            return i
+            return (i, Some(Complex { real: x, imag: y }))
         }
     }
-    return max_iter
+    (max_iter, None)
+}
+/// continuous iteration count from the escape iteration and z
+pub fn smooth_count(i: usize, z: Complex) -> f32 {
+    i as f32 + 1.0 - z.magnitude().ln().log2()
 }
 
-fn mt_generate_iter_counts(pm: &PixelMapper, width: usize, height: usize, max_iter: u16) -> Grid<u16> {
-    let mut g = Grid::new(width, height, 0u16);
+fn mt_generate_iter_counts(pm: &PixelMapper, width: usize, height: usize, max_iter: u32) -> Grid<u32> {
+    mt_generate_iter_counts_by(width, height, max_iter, |x, y| pm.map_centre(x, y))
+}
+/// as mt_generate_iter_counts, with each grid cell mapped to the plane by map
+pub fn mt_generate_iter_counts_by(width: usize, height: usize, max_iter: u32, map: impl Fn(usize, usize) -> Complex + Sync) -> Grid<u32> {
+    let mut g = Grid::new(width, height, 0u32);
 
     g.par_iter_rows_mut().for_each(|(y, row)| {
         row.iter_mut().enumerate().for_each(|(x, px)| {
-            *px = do_point_optimised(pm.map(x, y), max_iter as usize) as u16
+            *px = do_point_optimised(map(x, y), max_iter as usize) as u32
         })
     });
 
     g
 }
-pub fn mt_generate_tables(pm: &PixelMapper, width: usize, height: usize, max_iter: u16) -> (Grid<u16>, Vec<f32>) {
+pub fn mt_generate_tables(pm: &PixelMapper, width: usize, height: usize, max_iter: u32) -> (Grid<u32>, Vec<f32>) {
     let ic = mt_generate_iter_counts(pm, width, height, max_iter);
+    let h = histogram(&ic, max_iter);
+    (ic, h)
+}
+/// the cumulative colouring table for some iteration counts
+pub fn histogram(ic: &Grid<u32>, max_iter: u32) -> Vec<f32> {
     let mut h = Vec::new(); h.resize(max_iter as usize, 0usize);
     let mut total = 0usize;
 
@@ -94,25 +132,64 @@ pub fn mt_generate_tables(pm: &PixelMapper, width: usize, height: usize, max_ite
         h[count as usize] += 1;
     });
 
-    let h = accumulate_normalise_iterations(&h, total);
+    accumulate_normalise_iterations(&h, total)
+}
 
-    (ic, h)
+/// how iteration counts become positions in the palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapping {
+    /// cumulative histogram of the image, spreads the palette evenly over its pixels
+    Histogram,
+    /// i / max_iter
+    Linear,
+    /// ln(1 + i) / ln(1 + max_iter)
+    Log,
+}
+impl Mapping {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mapping::Histogram => "histogram",
+            Mapping::Linear => "linear",
+            Mapping::Log => "log",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "histogram" => Some(Mapping::Histogram),
+            "linear" => Some(Mapping::Linear),
+            "log" => Some(Mapping::Log),
+            _ => None
+        }
+    }
+    /// the table for mappings that don't depend on the image
+    pub fn fixed(self, max_iter: u32) -> Option<Vec<f32>> {
+        let m = max_iter as f32;
+        match self {
+            Mapping::Histogram => None,
+            Mapping::Linear => Some((0..max_iter).map(|i| i as f32 / m).collect()),
+            Mapping::Log => Some((0..max_iter).map(|i| (i as f32).ln_1p() / m.ln_1p()).collect()),
+        }
+    }
+    /// the colouring table for some iteration counts
+    pub fn table(self, ic: &Grid<u32>, max_iter: u32) -> Vec<f32> {
+        self.fixed(max_iter).unwrap_or_else(|| histogram(ic, max_iter))
+    }
 }
 
-pub fn generate_iteration_tables(pm: &PixelMapper, width: usize, height: usize, max_iter: u16) -> (Grid<u16>, Vec<f32>) {
-    let mut g = Grid::new(width, height, 0u16);
+pub fn generate_iteration_tables(pm: &PixelMapper, width: usize, height: usize, max_iter: u32) -> (Grid<u32>, Vec<f32>) {
+    let mut g = Grid::new(width, height, 0u32);
     let mut h = Vec::new(); h.resize(max_iter as usize, 0usize);
     let mut total = 0usize;
 
     for (x, y, v) in g.iter_coords_mut() {
-        match do_point(pm.map(x, y), max_iter as usize) {
+        match do_point(pm.map_centre(x, y), max_iter as usize) {
             Some(i) => {
-                *v = i as u16;
+                *v = i as u32;
                 h[i] += 1;
                 total += 1
             }
             None => {
-                *v = u16::MAX
+                *v = u32::MAX
             }
         }
     }
@@ -132,13 +209,97 @@ fn accumulate_normalise_iterations(h: &Vec<usize>, total: usize) -> Vec<f32> {
     v
 }
 
-pub fn draw_into_buffer(pm: &PixelMapper, width: usize, height: usize, buffer: &mut [u32], max_iter: u16) {
+const AUTO_PROBE_WIDTH: usize = 256;
+const AUTO_MAX_ITER: u32 = 1 << 24;
+/// stop raising max_iter once the boundary fraction moves less than this
+const AUTO_SETTLED: f32 = 0.001;
+
+/// picks a max_iter for a view by probing it at low resolution
+/// starts from a guess based on zoom depth, then doubles until the fraction of
+/// unescaped pixels on the edge of the set stops changing
+pub fn auto_max_iter(pm: &PixelMapper, width: usize, height: usize, radius: f32) -> u32 {
+    let pw = width.min(AUTO_PROBE_WIDTH);
+    let ph = (height * pw / width).max(1);
+    let probe = pm.scale(pw as f32 / width as f32);
+
+    let depth = (1.0 / radius).log10().max(0.0);
+    let mut max_iter = (100.0 + 200.0 * depth) as u32;
+    let mut last = boundary_fraction(&mt_generate_iter_counts(&probe, pw, ph, max_iter), max_iter);
+    while max_iter < AUTO_MAX_ITER {
+        let next = (max_iter * 2).min(AUTO_MAX_ITER);
+        let f = boundary_fraction(&mt_generate_iter_counts(&probe, pw, ph, next), next);
+        if (last - f).abs() < AUTO_SETTLED {
+            break
+        }
+        (max_iter, last) = (next, f);
+    }
+    max_iter
+}
+/// fraction of pixels that didn't escape but have a neighbour that did
+fn boundary_fraction(g: &Grid<u32>, max_iter: u32) -> f32 {
+    let (w, h) = (g.width(), g.height());
+    let inside = |x: usize, y: usize| g.get(x, y) >= max_iter;
+    let mut count = 0usize;
+    for (x, y, _) in g.iter_coords().filter(|(_, _, c)| **c >= max_iter) {
+        let edge = (x > 0 && !inside(x - 1, y)) || (x + 1 < w && !inside(x + 1, y))
+            || (y > 0 && !inside(x, y - 1)) || (y + 1 < h && !inside(x, y + 1));
+        if edge {
+            count += 1
+        }
+    }
+    count as f32 / (w * h) as f32
+}
+
+/// colours a grid from mt_generate_tables into an image of the same size
+pub fn colour_tables(g: &Grid<u32>, h: &[f32], width: u32, height: u32, palette: Palette) -> image::Rgb32FImage {
+    let mut i = image::Rgb32FImage::new(width, height);
+    i.pixels_mut().zip(g.iter()).for_each(|(p, i)| {
+        *p = palette.colour(h.get(i as usize).copied())
+    });
+    i
+}
+
+pub fn draw_into_buffer(pm: &PixelMapper, width: usize, height: usize, buffer: &mut [u32], max_iter: u32) {
     let (g, h) = generate_iteration_tables(pm, width, height, max_iter);
 
     g.iter().zip(buffer.iter_mut()).for_each(|(i, p)| {
         use image::Rgb;
-        let Rgb([r, g, b]) = h_palette(h.get(i as usize).copied());
+        let Rgb([r, g, b]) = to_rgb8(h_palette(h.get(i as usize).copied()));
         *p = u32::from_be_bytes([0, r, g, b])
     });
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn large_max_iter() {
+        // just outside the cardioid and the period 2 bulb where they touch, escaping well past u16
+        let c = Complex { real: -0.749_999_94, imag: 0.000_357_26 };
+        let g = mt_generate_iter_counts_by(1, 1, 200_000, |_, _| c);
+        assert_eq!(g.get(0, 0), 158_286);
+        assert_eq!(histogram(&g, 200_000).len(), 200_000);
+    }
+    #[test]
+    fn escape_matches_counts() {
+        let pm = PixelMapper::new_radx(Complex { real: -0.75, imag: 0.1 }, 0.05, 0.3, 40, 30);
+        for (x, y) in (0..40).flat_map(|x| (0..30).map(move |y| (x, y))) {
+            let c = pm.map(x, y);
+            let (i, z) = do_point_escape(c, 500);
+            assert_eq!(i, do_point_optimised(c, 500));
+            assert_eq!(z.is_some(), i < 500);
+            if let Some(z) = z {
+                let s = smooth_count(i, z);
+                assert!(s >= i as f32 && s <= i as f32 + 2.0, "{} {}", i, s);
+            }
+        }
+    }
+    #[test]
+    fn auto_iters_grow_with_depth() {
+        let c = Complex { real: -0.743_643_9, imag: 0.131_825_91 };
+        let shallow = auto_max_iter(&PixelMapper::new_radx(c, 1.0, 0.0, 64, 36), 64, 36, 1.0);
+        let deep = auto_max_iter(&PixelMapper::new_radx(c, 1e-4, 0.0, 64, 36), 64, 36, 1e-4);
+        assert!(deep > shallow, "{} {}", deep, shallow);
+    }
+}
@@ -0,0 +1,106 @@
+use image::{Rgb32FImage, ImageBuffer};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::utils::*;
+use crate::params::ViewParams;
+
+/// prefix for the png text chunk keywords, so other tools' metadata doesn't get mixed in
+const TEXT_PREFIX: &str = "fractal_window:";
+
+/// saves a render, picking the format by extension
+/// png is 8 or 16 bit depending on depth and gets the view parameters embedded as text chunks,
+/// tiff is 16 bit, exr and pfm are linear float, anything else goes through image as 8 bit
+pub fn save_image(path: &str, i: &Rgb32FImage, params: &ViewParams, depth: u8) -> Result<(), String> {
+    if is_png(path) {
+        save_png(path, i, params, depth)
+    }
+    else if has_extension(path, "tif") || has_extension(path, "tiff") {
+        let i: ImageBuffer<image::Rgb<u16>, _> = ImageBuffer::from_fn(i.width(), i.height(), |x, y| to_rgb16(*i.get_pixel(x, y)));
+        i.save(path).map_err(|e| e.to_string())
+    }
+    else if has_extension(path, "exr") {
+        linear(i).save(path).map_err(|e| e.to_string())
+    }
+    else if has_extension(path, "pfm") {
+        save_pfm(path, &linear(i))
+    }
+    else {
+        to_rgb8_image(i).save(path).map_err(|e| e.to_string())
+    }
+}
+
+pub fn to_rgb8_image(i: &Rgb32FImage) -> image::RgbImage {
+    ImageBuffer::from_fn(i.width(), i.height(), |x, y| to_rgb8(*i.get_pixel(x, y)))
+}
+
+pub fn has_extension(path: &str, ext: &str) -> bool {
+    Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+pub fn is_png(path: &str) -> bool {
+    has_extension(path, "png")
+}
+
+fn linear(i: &Rgb32FImage) -> Rgb32FImage {
+    let mut i = i.clone();
+    i.pixels_mut().for_each(|p| p.0 = p.0.map(srgb_to_linear));
+    i
+}
+
+/// a png encoder with the parameters already attached, for callers that write the rows themselves
+pub fn png_encoder(path: &str, width: u32, height: u32, params: &ViewParams, depth: u8) -> Result<png::Encoder<'static, BufWriter<File>>, String> {
+    let f = File::create(path).map_err(|e| e.to_string())?;
+    let mut e = png::Encoder::new(BufWriter::new(f), width, height);
+    e.set_color(png::ColorType::Rgb);
+    e.set_depth(if depth == 16 { png::BitDepth::Sixteen } else { png::BitDepth::Eight });
+    for (k, v) in params.to_pairs() {
+        e.add_text_chunk(format!("{}{}", TEXT_PREFIX, k), v).map_err(|e| e.to_string())?;
+    }
+    Ok(e)
+}
+/// png sample bytes for some pixels, big endian at 16 bit
+pub fn png_bytes<'a>(pixels: impl Iterator<Item = &'a image::Rgb<f32>>, depth: u8, out: &mut Vec<u8>) {
+    for p in pixels {
+        if depth == 16 {
+            to_rgb16(*p).0.iter().for_each(|v| out.extend(v.to_be_bytes()))
+        }
+        else {
+            out.extend(to_rgb8(*p).0)
+        }
+    }
+}
+
+fn save_png(path: &str, i: &Rgb32FImage, params: &ViewParams, depth: u8) -> Result<(), String> {
+    let mut w = png_encoder(path, i.width(), i.height(), params, depth)?.write_header().map_err(|e| e.to_string())?;
+    let mut data = Vec::new();
+    png_bytes(i.pixels(), depth, &mut data);
+    w.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+/// portable float map, which is stored bottom row first
+fn save_pfm(path: &str, i: &Rgb32FImage) -> Result<(), String> {
+    let f = File::create(path).map_err(|e| e.to_string())?;
+    let mut f = BufWriter::new(f);
+    write!(f, "PF\n{} {}\n-1.0\n", i.width(), i.height()).map_err(|e| e.to_string())?;
+    for row in i.rows().rev() {
+        for p in row {
+            p.0.iter().try_for_each(|v| f.write_all(&v.to_le_bytes())).map_err(|e| e.to_string())?;
+        }
+    }
+    f.flush().map_err(|e| e.to_string())
+}
+
+/// reads the view parameters back out of a png written by save_image
+pub fn load_png_params(path: &str) -> Result<ViewParams, String> {
+    let f = File::open(path).map_err(|e| e.to_string())?;
+    let r = png::Decoder::new(f).read_info().map_err(|e| e.to_string())?;
+    let pairs: Vec<_> = r.info().uncompressed_latin1_text.iter()
+        .filter_map(|c| Some((c.keyword.strip_prefix(TEXT_PREFIX)?, c.text.as_str())))
+        .collect();
+    if pairs.is_empty() {
+        return Err(format!("{} has no render parameters", path))
+    }
+    ViewParams::from_pairs(pairs)
+}
@@ -0,0 +1,223 @@
+use crate::utils::*;
+use crate::mandelbrot::{Formula, Mapping};
+use crate::render::{Sampling, Filter};
+use crate::pixelmapper::Projection;
+
+/// bump this when the meaning of an existing field changes
+/// adding a field doesn't need a bump, missing fields fall back to their defaults
+pub const PARAMS_VERSION: u32 = 1;
+
+/// version of the share string layout, written as its prefix
+const SHARE_VERSION: &str = "fw1";
+
+/// everything needed to reproduce a render
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewParams {
+    pub centre: Complex,
+    pub radius: f32,
+    pub angle: f32,
+    /// for exponential maps radius is the outside edge and height the rows down from it
+    pub projection: Projection,
+
+    pub width: u32,
+    pub height: u32,
+    pub scale_divisor: f32,
+
+    pub formula: Formula,
+    pub palette: Palette,
+    /// shifts colouring along the palette, for cycling it in animations
+    pub palette_offset: f32,
+    pub mapping: Mapping,
+    pub max_iter: usize,
+    pub aa: usize,
+    pub sampling: Sampling,
+    pub filter: Filter,
+    /// threshold for adaptive antialiasing, none means every pixel gets aa*aa samples
+    pub adaptive: Option<f32>,
+}
+impl Default for ViewParams {
+    fn default() -> Self {
+        Self {
+            centre: crate::STARTING_CENTRE,
+            radius: crate::STARTING_RADIUS,
+            angle: crate::STARTING_ANGLE,
+            projection: Projection::Linear,
+
+            width: crate::STARTING_WIDTH,
+            height: crate::STARTING_HEIGHT,
+            scale_divisor: 3.0,
+
+            formula: Formula::Mandelbrot,
+            palette: Palette::Blue,
+            palette_offset: 0.0,
+            mapping: Mapping::Histogram,
+            max_iter: 100,
+            aa: 1,
+            sampling: Sampling::Grid,
+            filter: Filter::Box,
+            adaptive: None,
+        }
+    }
+}
+impl ViewParams {
+    /// key/value pairs, in file order. floats are written with rust's shortest round-tripping repr
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("version", PARAMS_VERSION.to_string()),
+            ("centre", format!("{} {}", self.centre.real, self.centre.imag)),
+            ("radius", self.radius.to_string()),
+            ("angle", self.angle.to_string()),
+            ("projection", self.projection.name().to_owned()),
+            ("resolution", format!("{} {}", self.width, self.height)),
+            ("scale_divisor", self.scale_divisor.to_string()),
+            ("formula", self.formula.name().to_owned()),
+            ("palette", self.palette.name().to_owned()),
+            ("palette_offset", self.palette_offset.to_string()),
+            ("mapping", self.mapping.name().to_owned()),
+            ("max_iter", self.max_iter.to_string()),
+            ("aa", self.aa.to_string()),
+            ("sampling", self.sampling.name().to_owned()),
+            ("filter", self.filter.name().to_owned()),
+            ("adaptive", self.adaptive.map_or(String::from("off"), |t| t.to_string())),
+        ]
+    }
+    /// unknown keys are skipped (with a warning) so newer files still load as far as possible
+    pub fn from_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self, String> {
+        let mut p = Self::default();
+        for (k, v) in pairs {
+            let bad = || format!("bad value for {}: {}", k, v);
+            match k {
+                "version" => {
+                    let version: u32 = v.parse().map_err(|_| bad())?;
+                    if version > PARAMS_VERSION {
+                        println!("warning: parameters are version {}, newer than {}", version, PARAMS_VERSION)
+                    }
+                }
+                "centre" => {
+                    let (real, imag) = parse_pair(v).ok_or_else(bad)?;
+                    p.centre = Complex { real, imag }
+                }
+                "radius" => p.radius = v.parse().map_err(|_| bad())?,
+                "angle" => p.angle = v.parse().map_err(|_| bad())?,
+                "projection" => p.projection = Projection::from_name(v).ok_or_else(bad)?,
+                "resolution" => (p.width, p.height) = parse_pair(v).ok_or_else(bad)?,
+                "scale_divisor" => p.scale_divisor = v.parse().map_err(|_| bad())?,
+                "formula" => p.formula = Formula::from_name(v).ok_or_else(bad)?,
+                "palette" => p.palette = Palette::from_name(v).ok_or_else(bad)?,
+                "palette_offset" => p.palette_offset = v.parse().map_err(|_| bad())?,
+                "mapping" => p.mapping = Mapping::from_name(v).ok_or_else(bad)?,
+                "max_iter" => p.max_iter = v.parse().map_err(|_| bad())?,
+                "aa" => p.aa = v.parse().map_err(|_| bad())?,
+                "sampling" => p.sampling = Sampling::from_name(v).ok_or_else(bad)?,
+                "filter" => p.filter = Filter::from_name(v).ok_or_else(bad)?,
+                "adaptive" => p.adaptive = match v {
+                    "off" => None,
+                    t => Some(t.parse().map_err(|_| bad())?)
+                },
+                _ => println!("warning: unknown parameter {}", k)
+            }
+        }
+        Ok(p)
+    }
+
+    /// `key = value` lines, with comments starting with #
+    pub fn to_file_string(&self) -> String {
+        let mut s = String::from("# fractal_window view parameters\n");
+        for (k, v) in self.to_pairs() {
+            s += &format!("{} = {}\n", k, v);
+        }
+        s
+    }
+    pub fn from_file_string(s: &str) -> Result<Self, String> {
+        let mut pairs = Vec::new();
+        for (n, l) in s.lines().enumerate() {
+            let l = l.split('#').next().unwrap_or("").trim();
+            if l.is_empty() {
+                continue
+            }
+            let (k, v) = l.split_once('=').ok_or_else(|| format!("line {}: expected key = value", n + 1))?;
+            pairs.push((k.trim(), v.trim()));
+        }
+        Self::from_pairs(pairs)
+    }
+
+    /// one line holding the location, formula, palette and iters
+    /// floats are written as their bit patterns in hex so nothing is lost
+    pub fn to_share_string(&self) -> String {
+        format!(
+            "{}:{:08x}:{:08x}:{:08x}:{:08x}:{}:{}:{}",
+            SHARE_VERSION,
+            self.centre.real.to_bits(), self.centre.imag.to_bits(),
+            self.radius.to_bits(), self.angle.to_bits(),
+            self.formula.name(), self.palette.name(), self.max_iter
+        )
+    }
+    /// anything the share string doesn't hold (resolution, aa) is taken from base
+    pub fn from_share_string(s: &str, base: &ViewParams) -> Result<Self, String> {
+        let mut i = s.trim().split(':');
+        let version = i.next().unwrap_or("");
+        if version != SHARE_VERSION {
+            return Err(format!("unknown share string version {:?}", version))
+        }
+        let mut float = || i.next()
+            .and_then(|v| u32::from_str_radix(v, 16).ok())
+            .map(f32::from_bits)
+            .ok_or("bad share string");
+        let centre = Complex { real: float()?, imag: float()? };
+        let radius = float()?;
+        let angle = float()?;
+        let formula = i.next().and_then(Formula::from_name).ok_or("bad share string formula")?;
+        let palette = i.next().and_then(Palette::from_name).ok_or("bad share string palette")?;
+        let max_iter = i.next().and_then(|v| v.parse().ok()).ok_or("bad share string iters")?;
+        Ok(Self { centre, radius, angle, formula, palette, max_iter, .. base.clone() })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_file_string()).map_err(|e| e.to_string())
+    }
+    pub fn load(path: &str) -> Result<Self, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_file_string(&s)
+    }
+}
+
+fn parse_pair<T: std::str::FromStr>(s: &str) -> Option<(T, T)> {
+    let mut i = s.split_ascii_whitespace();
+    let a = i.next()?.parse().ok()?;
+    let b = i.next()?.parse().ok()?;
+    Some((a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn file_round_trip() {
+        let p = ViewParams {
+            centre: Complex { real: -0.743_643_9, imag: 0.131_825_91 },
+            radius: 1.234_567_9e-5,
+            angle: 0.3,
+            max_iter: 5000,
+            aa: 3,
+            .. ViewParams::default()
+        };
+        assert_eq!(ViewParams::from_file_string(&p.to_file_string()), Ok(p));
+    }
+    #[test]
+    fn share_round_trip() {
+        let p = ViewParams {
+            centre: Complex { real: -1.768_778_8, imag: -0.001_738_996 },
+            radius: 3.0e-7,
+            max_iter: 20000,
+            .. ViewParams::default()
+        };
+        let s = p.to_share_string();
+        assert_eq!(ViewParams::from_share_string(&s, &ViewParams::default()), Ok(p));
+        assert!(ViewParams::from_share_string("fw9:00", &ViewParams::default()).is_err());
+    }
+    #[test]
+    fn missing_and_unknown_fields() {
+        let p = ViewParams::from_file_string("version = 1\nradius = 0.5 # comment\nshiny = yes\n").unwrap();
+        assert_eq!(p, ViewParams { radius: 0.5, .. ViewParams::default() });
+    }
+}
@@ -1,14 +1,64 @@
 use crate::utils::*;
 
+/// how pixels are laid out over the plane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// an ordinary rectangular view
+    Linear,
+    /// log-polar around the centre: x goes once round in angle, y goes down in log radius
+    /// pixels are square in log-polar space, so each row is 2pi / width smaller in log radius than the last
+    ExpMap,
+}
+impl Projection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Projection::Linear => "linear",
+            Projection::ExpMap => "expmap",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "linear" => Some(Projection::Linear),
+            "expmap" => Some(Projection::ExpMap),
+            _ => None
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PixelMapper {
     topleft: Complex, // complex number at image 0,0
     x_px_dist: Complex, // offset represented by 1 pixel in the x direction
     y_px_dist: Complex, // as above for y direction
+    expmap: Option<ExpMap>,
+}
+/// the exponential map's layout, used instead of the linear fields when set
+#[derive(Debug, Copy, Clone)]
+struct ExpMap {
+    centre: Complex,
+    /// log radius at y = 0
+    log_radius: f32,
+    /// angle at x = 0
+    angle: f32,
+    /// change in angle and log radius per pixel
+    step: f32,
 }
 impl PixelMapper {
     pub fn map(&self, x: usize, y: usize) -> Complex {
-        let offset = (self.x_px_dist * x as f32) - (self.y_px_dist * y as f32);
+        self.map_f(x as f32, y as f32)
+    }
+    /// the centre of pixel (x, y), where renders put a single sample
+    pub fn map_centre(&self, x: usize, y: usize) -> Complex {
+        self.map_f(x as f32 + 0.5, y as f32 + 0.5)
+    }
+    /// as map, but for positions between pixels
+    pub fn map_f(&self, x: f32, y: f32) -> Complex {
+        if let Some(e) = self.expmap {
+            let (s, c) = (e.angle + x * e.step).sin_cos();
+            let r = (e.log_radius - y * e.step).exp();
+            return e.centre + Complex { real: c * r, imag: s * r }
+        }
+        let offset = (self.x_px_dist * x) - (self.y_px_dist * y);
         self.topleft + offset
     }
 
@@ -33,19 +83,83 @@ impl PixelMapper {
         let y_px_dist = Complex { real: yr, imag: yi };
 
         Self {
-            topleft, x_px_dist, y_px_dist
+            topleft, x_px_dist, y_px_dist, expmap: None
         }
     }
+    /// exponential map around centre, wi pixels round, with the top edge at radius
+    /// rows reach down as far as the caller renders them
+    pub fn new_expmap(centre: Complex, radius: f32, angle: f32, wi: u32) -> Self {
+        let step = std::f32::consts::TAU / wi as f32;
+        Self {
+            expmap: Some(ExpMap { centre, log_radius: radius.ln(), angle, step }),
+            .. Self::new_radx(centre, radius, angle, wi, wi)
+        }
+    }
+    /// rows an exponential map wi pixels round needs to go from outer down to inner radius
+    pub fn expmap_rows(outer: f32, inner: f32, wi: u32) -> u32 {
+        ((outer / inner).ln() * wi as f32 / std::f32::consts::TAU).ceil().max(1.0) as u32
+    }
+    /// as new_radx, with the size given any other way
+    pub fn new_frame(centre: Complex, frame: Frame, angle: f32, wi: u32, hi: u32) -> Self {
+        Self::new_radx(centre, frame.radx(wi, hi), angle, wi, hi)
+    }
+    /// unrotated view fitting both corners, with the image aspect ratio kept
+    pub fn new_corners(a: Complex, b: Complex, wi: u32, hi: u32) -> Self {
+        let (centre, radius) = corners_view(a, b, wi, hi);
+        Self::new_radx(centre, radius, 0.0, wi, hi)
+    }
+
+    /// the same mapping with pixel (x, y) moved to 0,0
+    pub fn crop(&self, x: f32, y: f32) -> Self {
+        Self {
+            topleft: self.map_f(x, y),
+            expmap: self.expmap.map(|e| ExpMap { angle: e.angle + x * e.step, log_radius: e.log_radius - y * e.step, .. e }),
+            .. *self
+        }
+    }
+
     /// scale > 1 means increase resolution
     pub fn scale(&self, scale: f32) -> Self {
         Self {
             x_px_dist: self.x_px_dist / scale,
             y_px_dist: self.y_px_dist / scale,
+            expmap: self.expmap.map(|e| ExpMap { step: e.step / scale, .. e }),
             .. *self
         }
     }
 }
 
+/// ways of saying how big a view is
+#[derive(Debug, Clone, Copy)]
+pub enum Frame {
+    /// half the width, what new_radx takes
+    RadX(f32),
+    /// half the height
+    RadY(f32),
+    /// magnification relative to the starting view
+    Zoom(f32),
+    /// width of one pixel
+    PixelSize(f32),
+}
+impl Frame {
+    pub fn radx(self, wi: u32, hi: u32) -> f32 {
+        match self {
+            Frame::RadX(r) => r,
+            Frame::RadY(r) => r * wi as f32 / hi as f32,
+            Frame::Zoom(z) => crate::STARTING_RADIUS / z,
+            Frame::PixelSize(s) => s * wi as f32 / 2.0,
+        }
+    }
+}
+
+/// centre and horizontal radius of the smallest unrotated view containing both corners
+pub fn corners_view(a: Complex, b: Complex, wi: u32, hi: u32) -> (Complex, f32) {
+    let centre = (a + b) / 2.0;
+    let rx = (a.real - b.real).abs() / 2.0;
+    let ry = (a.imag - b.imag).abs() / 2.0;
+    (centre, rx.max(Frame::RadY(ry).radx(wi, hi)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,5 +175,40 @@ mod tests {
         let pm = PixelMapper::new_radx(Complex::ZERO, 2.0, std::f32::consts::PI * 1.5, 4, 4);
         assert_eq!(pm.topleft, Complex { real: 2.0, imag: 2.0 });
     }
+    #[test]
+    fn map_centre_is_half_a_pixel_in() {
+        let pm = PixelMapper::new_radx(Complex::ZERO, 2.0, 0.0, 4, 4);
+        assert_eq!(pm.map_centre(1, 1), Complex { real: -0.5, imag: 0.5 });
+    }
+    #[test]
+    fn frames() {
+        let pm = PixelMapper::new_radx(Complex::ZERO, 2.0, 0.0, 4, 2);
+        let c = Complex::ZERO;
+        assert_eq!(PixelMapper::new_frame(c, Frame::RadY(1.0), 0.0, 4, 2).topleft, pm.topleft);
+        assert_eq!(PixelMapper::new_frame(c, Frame::PixelSize(1.0), 0.0, 4, 2).topleft, pm.topleft);
+        assert_eq!(PixelMapper::new_frame(c, Frame::Zoom(crate::STARTING_RADIUS / 2.0), 0.0, 4, 2).topleft, pm.topleft);
+
+        let pm = PixelMapper::new_corners(Complex { real: -2.0, imag: 0.5 }, Complex { real: 2.0, imag: -0.5 }, 4, 2);
+        assert_eq!(pm.topleft, Complex { real: -2.0, imag: 1.0 });
+    }
+    #[test]
+    fn expmap_rows_shrink() {
+        let c = Complex { real: -1.0, imag: 0.5 };
+        let pm = PixelMapper::new_expmap(c, 2.0, 0.0, 8);
+        assert!(((pm.map_f(0.0, 0.0) - c).magnitude() - 2.0).abs() < 1e-6);
+        // a whole turn down is a factor of e^2pi smaller
+        let r = (pm.map_f(3.0, 8.0) - c).magnitude();
+        assert!((r / (2.0 * (-std::f32::consts::TAU).exp()) - 1.0).abs() < 1e-4, "{}", r);
+        let cropped = pm.crop(2.0, 3.0);
+        assert!((cropped.map_f(1.0, 1.0) - pm.map_f(3.0, 4.0)).magnitude() < 1e-6);
+        assert_eq!(PixelMapper::expmap_rows(1.0, 0.01, 100), 74);
+    }
+    #[test]
+    fn map_f_centre() {
+        let c = Complex { real: -1.0, imag: 0.5 };
+        let pm = PixelMapper::new_radx(c, 1.0, 0.0, 8, 4);
+        assert_eq!(pm.map_f(4.0, 2.0), c);
+        assert_eq!(pm.map_f(4.5, 2.0), Complex { real: -0.875, imag: 0.5 });
+    }
 }
 
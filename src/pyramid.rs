@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use crate::utils::*;
+use crate::render::Renderer;
+use crate::pixelmapper::PixelMapper;
+use crate::output::to_rgb8_image;
+
+/// the deepest level where f32 coordinates still tell neighbouring pixels apart,
+/// for tiles tile pixels across with level 0 covering radius either side of centre
+pub fn max_level(centre: Complex, radius: f32, tile: usize) -> u32 {
+    // coordinates are about this big, so they're good to about extent * EPSILON
+    let extent = centre.real.abs().max(centre.imag.abs()) + radius;
+    let px = 2.0 * radius / tile as f32;
+    (px / (extent * f32::EPSILON)).log2().floor().max(0.0) as u32
+}
+
+/// xyz tiles under dir/z/x/y.png for levels 0..levels
+/// level 0 is one tile covering a square around the view, each level after doubles the tiles across
+/// every tile shares the colouring of level 0 so colours match between levels
+pub fn export_xyz(r: &Renderer, centre: Complex, radius: f32, angle: f32, dir: &str, levels: u32, tile: usize) -> Result<(), String> {
+    let deepest = max_level(centre, radius, tile);
+    if levels > deepest + 1 {
+        return Err(format!("at most {} levels at this view before f32 runs out of precision", deepest + 1))
+    }
+    let world = |z: u32| {
+        let n = tile.checked_shl(z).and_then(|n| u32::try_from(n).ok()).ok_or("level too large")?;
+        Ok::<_, String>(PixelMapper::new_radx(centre, radius, angle, n, n))
+    };
+    let h = Renderer { pm: world(0)?, width: tile, height: tile, .. *r }.prepass_histogram();
+
+    for z in 0..levels {
+        let pm = world(z)?;
+        let count = 1usize.checked_shl(z).ok_or("level too large")?;
+        let level = Renderer { pm, width: tile * count, height: tile * count, .. *r };
+        for x in 0..count {
+            let col = Path::new(dir).join(z.to_string()).join(x.to_string());
+            std::fs::create_dir_all(&col).map_err(|e| e.to_string())?;
+            for y in 0..count {
+                let i = level.render_window(&h, x * tile, y * tile, tile, tile);
+                to_rgb8_image(&i).save(col.join(format!("{}.png", y))).map_err(|e| e.to_string())?;
+            }
+        }
+        println!("level {} done ({} tiles)", z, count * count);
+    }
+    Ok(())
+}
+
+/// a deepzoom image, path.dzi plus tiles in path_files/level/col_row.png
+/// the top level is the renderer's full size, each level below halves it down to 1x1
+pub fn export_dzi(r: &Renderer, path: &str, tile: usize) -> Result<(), String> {
+    let base = path.strip_suffix(".dzi").ok_or("deepzoom output should end in .dzi")?;
+    let files = format!("{}_files", base);
+    let (w, hi) = (r.width, r.height);
+    let max_level = (w.max(hi) as f32).log2().ceil() as u32;
+    let h = r.prepass_histogram();
+
+    for level in 0..=max_level {
+        let scale = 0.5f32.powi((max_level - level) as i32);
+        let lw = ((w as f32 * scale).ceil() as usize).max(1);
+        let lh = ((hi as f32 * scale).ceil() as usize).max(1);
+        let lr = Renderer { pm: r.pm.scale(lw as f32 / w as f32), width: lw, height: lh, .. *r };
+
+        let dir = Path::new(&files).join(level.to_string());
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        for col in 0..lw.div_ceil(tile) {
+            for row in 0..lh.div_ceil(tile) {
+                let (x, y) = (col * tile, row * tile);
+                let i = lr.render_window(&h, x, y, tile.min(lw - x), tile.min(lh - y));
+                to_rgb8_image(&i).save(dir.join(format!("{}_{}.png", col, row))).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    let dzi = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" Overlap=\"0\" TileSize=\"{}\">\n  <Size Width=\"{}\" Height=\"{}\"/>\n</Image>\n",
+        tile, w, hi
+    );
+    std::fs::write(path, dzi).map_err(|e| e.to_string())?;
+    println!("wrote {} levels", max_level + 1);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// raw iteration counts saved next to a render, so it can be recoloured without being recomputed
+// the file is the magic and a version byte, the view parameters as text (length prefixed),
+// the sample grid's width and height, the bytes per count (2 or 4) and then the counts,
+// all little endian. the samples are the aa*aa per pixel ones the render was filtered from
+
+use std::path::Path;
+
+use crate::utils::*;
+use crate::grid::Grid;
+use crate::params::ViewParams;
+use crate::render::Renderer;
+use crate::mandelbrot::Mapping;
+use crate::output;
+
+const MAGIC: &[u8] = b"fwraw";
+const RAW_VERSION: u8 = 1;
+
+/// where the raw data for an image goes
+pub fn raw_path(image: &str) -> String {
+    Path::new(image).with_extension("fwraw").to_string_lossy().into_owned()
+}
+
+pub fn save(path: &str, g: &Grid<u32>, params: &ViewParams) -> Result<(), String> {
+    std::fs::write(path, to_bytes(g, params)).map_err(|e| e.to_string())
+}
+pub fn load(path: &str) -> Result<(ViewParams, Grid<u32>), String> {
+    from_bytes(&std::fs::read(path).map_err(|e| e.to_string())?)
+}
+
+fn to_bytes(g: &Grid<u32>, params: &ViewParams) -> Vec<u8> {
+    let text = params.to_file_string();
+    // counts only reach max_iter, so most renders fit in 2 bytes
+    let wide = g.iter().any(|c| c > u16::MAX as u32);
+    let mut b = Vec::with_capacity(text.len() + g.width() * g.height() * 4 + 32);
+    b.extend_from_slice(MAGIC);
+    b.push(RAW_VERSION);
+    b.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    b.extend_from_slice(text.as_bytes());
+    b.extend_from_slice(&(g.width() as u32).to_le_bytes());
+    b.extend_from_slice(&(g.height() as u32).to_le_bytes());
+    b.push(if wide { 4 } else { 2 });
+    for c in g.iter() {
+        if wide {
+            b.extend_from_slice(&c.to_le_bytes())
+        }
+        else {
+            b.extend_from_slice(&(c as u16).to_le_bytes())
+        }
+    }
+    b
+}
+fn from_bytes(b: &[u8]) -> Result<(ViewParams, Grid<u32>), String> {
+    let mut b = b.strip_prefix(MAGIC).ok_or("not a raw data file")?;
+    let mut take = |n: usize| -> Result<&[u8], String> {
+        if b.len() < n {
+            return Err(String::from("raw data file is truncated"))
+        }
+        let (head, rest) = b.split_at(n);
+        b = rest;
+        Ok(head)
+    };
+    let u32_le = |s: &[u8]| u32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+
+    let version = take(1)?[0];
+    if version != RAW_VERSION {
+        return Err(format!("raw data version {} isn't supported", version))
+    }
+    let len = u32_le(take(4)?) as usize;
+    let text = std::str::from_utf8(take(len)?).map_err(|e| e.to_string())?;
+    let params = ViewParams::from_file_string(text)?;
+    let (w, h) = (u32_le(take(4)?) as usize, u32_le(take(4)?) as usize);
+    let data = match take(1)?[0] {
+        2 => take(w * h * 2)?.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]) as u32).collect(),
+        4 => take(w * h * 4)?.chunks_exact(4).map(u32_le).collect(),
+        n => return Err(format!("bad count size {}", n))
+    };
+    if w == 0 {
+        return Err(String::from("raw data is empty"))
+    }
+    Ok((params, Grid::from_vec(data, w)))
+}
+
+/// colours raw data with a palette and mapping (the one it was rendered with if none), then saves it as an image
+pub fn recolour(data: &str, palette: Palette, mapping: Option<Mapping>, out: &str, depth: u8) -> Result<(), String> {
+    let (p, g) = load(data)?;
+    let params = ViewParams { palette, mapping: mapping.unwrap_or(p.mapping), .. p };
+    let aa = params.aa.max(1);
+    let (w, h) = (params.width as usize, params.height as usize);
+    if g.width() != w * aa || g.height() != h * aa {
+        return Err(format!("raw data is {}x{} samples, expected {}x{} at aa {}", g.width(), g.height(), w * aa, h * aa, aa))
+    }
+    let r = Renderer { adaptive: None, .. Renderer::new(&params)? };
+    output::save_image(out, &r.colour_samples(&g), &params, depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn round_trip() {
+        let p = ViewParams { aa: 2, .. ViewParams::default() };
+        for top in [1000, 100_000] {
+            let g = Grid::from_vec(vec![0, 5, top, 7, 8, 9], 3);
+            let (q, h) = from_bytes(&to_bytes(&g, &p)).unwrap();
+            assert_eq!(q, p);
+            assert_eq!(h.iter().collect::<Vec<_>>(), g.iter().collect::<Vec<_>>());
+            assert_eq!(h.width(), 3);
+        }
+        assert!(from_bytes(&to_bytes(&Grid::from_vec(vec![1, 2], 2), &p)[..20]).is_err());
+    }
+}
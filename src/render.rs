@@ -0,0 +1,504 @@
+use image::{Rgb, Rgb32FImage};
+use rayon::prelude::*;
+
+use crate::utils::*;
+use crate::grid::Grid;
+use crate::pixelmapper::{PixelMapper, Projection};
+use crate::mandelbrot::{self, Mapping};
+use crate::params::ViewParams;
+use crate::output;
+
+/// the colouring pre-pass for tiled renders uses at most this many pixels across
+const PREPASS_WIDTH: usize = 1024;
+/// output rows resolved together, sharing the colours of the samples their filters reach
+const RESOLVE_ROWS: usize = 16;
+
+/// where the aa*aa samples go inside each pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// evenly spaced
+    Grid,
+    /// one random point in each cell of the grid
+    Jitter,
+    /// R2 low-discrepancy points, shifted randomly per pixel
+    /// spreads samples within a pixel more evenly than jitter
+    R2,
+}
+impl Sampling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sampling::Grid => "grid",
+            Sampling::Jitter => "jitter",
+            Sampling::R2 => "r2",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "grid" => Some(Sampling::Grid),
+            "jitter" => Some(Sampling::Jitter),
+            "r2" => Some(Sampling::R2),
+            _ => None
+        }
+    }
+
+    /// position of sample (i, j) inside pixel (x, y), each coordinate from 0 to 1
+    /// x and y should be in the whole image, so windows of it get the same pattern
+    fn offset(self, x: usize, y: usize, i: usize, j: usize, aa: usize) -> (f32, f32) {
+        let n = aa as f32;
+        match self {
+            Sampling::Grid => ((i as f32 + 0.5) / n, (j as f32 + 0.5) / n),
+            Sampling::Jitter => {
+                let h = hash(x, y, i + j * aa);
+                ((i as f32 + unit(h)) / n, (j as f32 + unit(h >> 32)) / n)
+            }
+            Sampling::R2 => {
+                // plastic number constants for the R2 sequence
+                const A1: f32 = 0.754_877_7;
+                const A2: f32 = 0.569_840_3;
+                let h = hash(x, y, 0);
+                let k = (i + j * aa) as f32;
+                ((unit(h) + k * A1).fract(), (unit(h >> 32) + k * A2).fract())
+            }
+        }
+    }
+}
+
+/// splitmix64 over the inputs, so jittered renders come out the same every time
+fn hash(x: usize, y: usize, k: usize) -> u64 {
+    let mut z = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9)
+        ^ (k as u64).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+/// low 24 bits as a float in [0, 1)
+fn unit(h: u64) -> f32 {
+    (h & 0xff_ffff) as f32 / (1 << 24) as f32
+}
+
+/// how much a sample counts towards a pixel, by distance from the pixel centre in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    /// mitchell-netravali with B = C = 1/3
+    Mitchell,
+    /// lanczos with 2 lobes
+    Lanczos,
+}
+impl Filter {
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+            Filter::Lanczos => "lanczos",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "box" => Some(Filter::Box),
+            "tent" => Some(Filter::Tent),
+            "gaussian" => Some(Filter::Gaussian),
+            "mitchell" => Some(Filter::Mitchell),
+            "lanczos" => Some(Filter::Lanczos),
+            _ => None
+        }
+    }
+
+    pub fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::Lanczos => 2.0,
+        }
+    }
+    pub fn weight(self, d: f32) -> f32 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.0
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d,
+            Filter::Gaussian => (-2.0 * d * d).exp(),
+            Filter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let d2 = d * d;
+                let d3 = d2 * d;
+                if d < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * d3 + (-18.0 + 12.0 * b + 6.0 * c) * d2 + (6.0 - 2.0 * b)) / 6.0
+                }
+                else {
+                    ((-b - 6.0 * c) * d3 + (6.0 * b + 30.0 * c) * d2 + (-12.0 * b - 48.0 * c) * d + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+            Filter::Lanczos => sinc(d) * sinc(d / 2.0),
+        }
+    }
+}
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    }
+    else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// renders an image with aa*aa samples per pixel, weighted across pixel boundaries by a filter
+#[derive(Clone, Copy)]
+pub struct Renderer {
+    pub pm: PixelMapper,
+    pub width: usize,
+    pub height: usize,
+    pub max_iter: u32,
+    pub aa: usize,
+    pub sampling: Sampling,
+    pub filter: Filter,
+    /// if set, pixels start with one sample and only edges get more, see render_adaptive
+    pub adaptive: Option<f32>,
+    pub palette: Palette,
+    /// how far along the palette colouring starts, see Palette::colour_offset
+    pub palette_offset: f32,
+    pub mapping: Mapping,
+    /// where pixel (0, 0) is in the whole image, which seeds the sampling pattern
+    /// wraps for windows starting before the image
+    pub origin: (usize, usize),
+}
+impl Renderer {
+    /// a renderer for everything in some view parameters
+    pub fn new(p: &ViewParams) -> Result<Self, String> {
+        Ok(Self {
+            pm: match p.projection {
+                Projection::Linear => PixelMapper::new_radx(p.centre, p.radius, p.angle, p.width, p.height),
+                Projection::ExpMap => PixelMapper::new_expmap(p.centre, p.radius, p.angle, p.width),
+            },
+            width: p.width as usize,
+            height: p.height as usize,
+            max_iter: u32::try_from(p.max_iter).map_err(|_| format!("max_iter can be at most {}", u32::MAX))?,
+            aa: p.aa.max(1),
+            sampling: p.sampling,
+            filter: p.filter,
+            adaptive: p.adaptive,
+            palette: p.palette,
+            palette_offset: p.palette_offset,
+            mapping: p.mapping,
+            origin: (0, 0),
+        })
+    }
+
+    /// position of sample (i, j) of n*n inside pixel (x, y) of this renderer
+    fn offset(&self, x: usize, y: usize, i: usize, j: usize, n: usize) -> (f32, f32) {
+        self.sampling.offset(self.origin.0.wrapping_add(x), self.origin.1.wrapping_add(y), i, j, n)
+    }
+
+    /// how many pixels away from a pixel its filter reaches
+    fn margin(&self) -> usize {
+        (self.filter.radius() - 0.5).max(0.0).ceil() as usize
+    }
+
+    /// iteration counts for every sample in pixel rows y0..y1
+    /// sample (sx, sy) belongs to pixel (sx / aa, y0 + sy / aa)
+    pub fn sample_rows(&self, y0: usize, y1: usize) -> Grid<u32> {
+        let aa = self.aa;
+        mandelbrot::mt_generate_iter_counts_by(self.width * aa, (y1 - y0) * aa, self.max_iter, |sx, sy| {
+            let (x, y) = (sx / aa, y0 + sy / aa);
+            let (ox, oy) = self.offset(x, y, sx % aa, sy % aa, aa);
+            self.pm.map_f(x as f32 + ox, y as f32 + oy)
+        })
+    }
+
+    /// filters the samples from sample_rows(s0, _) into output rows y0..y1
+    /// the samples should reach margin() rows past y0..y1 where the image has them
+    pub fn resolve_rows(&self, g: &Grid<u32>, s0: usize, h: &[f32], y0: usize, y1: usize) -> Vec<Rgb<f32>> {
+        let mut acc = vec![Accum::default(); self.width * (y1 - y0)];
+        self.accumulate_rows(g, s0, h, y0, y1, &mut acc);
+        acc.iter().map(Accum::resolve).collect()
+    }
+    /// as resolve_rows, but adding the filtered samples to acc, so samples from several renders
+    /// of the same size (ie. the sub-frames of motion blur) resolve together
+    pub fn accumulate_rows(&self, g: &Grid<u32>, s0: usize, h: &[f32], y0: usize, y1: usize, acc: &mut [Accum]) {
+        let aa = self.aa;
+        let s1 = s0 + g.height() / aa;
+        let sw = g.width();
+        let colour = |count: u32| self.palette.colour_offset(h.get(count as usize).copied(), self.palette_offset).0.map(srgb_to_linear);
+
+        let m = self.margin();
+        acc[..self.width * (y1 - y0)].par_chunks_mut(self.width * RESOLVE_ROWS).enumerate().for_each(|(chunk, out)| {
+            let ya = y0 + chunk * RESOLVE_ROWS;
+            // colours of just the sample rows these rows' filters reach
+            let (r0, r1) = (ya.saturating_sub(m).max(s0), (ya + out.len() / self.width + m).min(s1));
+            let band: Vec<_> = ((r0 - s0) * aa * sw..(r1 - s0) * aa * sw).map(|i| colour(g.get(i % sw, i / sw))).collect();
+            for (k, acc) in out.iter_mut().enumerate() {
+                let (x, y) = (k % self.width, ya + k / self.width);
+                let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                for ny in y.saturating_sub(m).max(s0)..(y + m + 1).min(s1) {
+                    for nx in x.saturating_sub(m)..(x + m + 1).min(self.width) {
+                        for j in 0..aa {
+                            for i in 0..aa {
+                                let (ox, oy) = self.offset(nx, ny, i, j, aa);
+                                let w = self.filter.weight(nx as f32 + ox - cx) * self.filter.weight(ny as f32 + oy - cy);
+                                if w != 0.0 {
+                                    acc.add(band[((ny - r0) * aa + j) * sw + nx * aa + i], w)
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn render(&self) -> Rgb32FImage {
+        match self.adaptive {
+            Some(threshold) if self.aa > 1 => self.render_adaptive(threshold),
+            _ => self.render_samples().0
+        }
+    }
+
+    /// renders every pixel with aa*aa samples, also returning the samples so they can be recoloured later
+    pub fn render_samples(&self) -> (Rgb32FImage, Grid<u32>) {
+        let start = std::time::Instant::now();
+        let g = self.sample_rows(0, self.height);
+        println!("tables took {}ms", start.elapsed().as_millis());
+        (self.colour_samples(&g), g)
+    }
+    /// colours samples from sample_rows(0, height), with a table from the renderer's mapping
+    pub fn colour_samples(&self, g: &Grid<u32>) -> Rgb32FImage {
+        let start = std::time::Instant::now();
+        let h = self.mapping.table(g, self.max_iter);
+        let px = self.resolve_rows(g, 0, &h, 0, self.height);
+        println!("colouring took {}ms", start.elapsed().as_millis());
+        Rgb32FImage::from_fn(self.width as u32, self.height as u32, |x, y| px[x as usize + y as usize * self.width])
+    }
+    /// renders with a colouring table from somewhere else, ie. shared between tiles
+    pub fn render_with_histogram(&self, h: &[f32]) -> Rgb32FImage {
+        self.render_coloured_by(|_| h.to_vec())
+    }
+    /// renders with the colouring table table makes from the samples
+    pub fn render_coloured_by(&self, table: impl FnOnce(&Grid<u32>) -> Vec<f32>) -> Rgb32FImage {
+        let g = self.sample_rows(0, self.height);
+        let h = table(&g);
+        let px = self.resolve_rows(&g, 0, &h, 0, self.height);
+        Rgb32FImage::from_fn(self.width as u32, self.height as u32, |x, y| px[x as usize + y as usize * self.width])
+    }
+    /// renders a window of the image, from pixel (x, y), without seams against its neighbours
+    /// the filter gets the samples past the window's edges that it would have had in the full image
+    pub fn render_window(&self, h: &[f32], x: usize, y: usize, width: usize, height: usize) -> Rgb32FImage {
+        let m = self.margin();
+        let padded = Renderer {
+            pm: self.pm.crop(x as f32 - m as f32, y as f32 - m as f32),
+            width: width + 2 * m,
+            height: height + 2 * m,
+            origin: (self.origin.0.wrapping_add(x).wrapping_sub(m), self.origin.1.wrapping_add(y).wrapping_sub(m)),
+            .. *self
+        };
+        let i = padded.render_with_histogram(h);
+        image::imageops::crop_imm(&i, m as u32, m as u32, width as u32, height as u32).to_image()
+    }
+    /// colouring table from a low resolution render of the whole view
+    pub fn prepass_histogram(&self) -> Vec<f32> {
+        if let Some(t) = self.mapping.fixed(self.max_iter) {
+            return t
+        }
+        let pw = self.width.min(PREPASS_WIDTH);
+        let prepass = Renderer {
+            pm: self.pm.scale(pw as f32 / self.width as f32),
+            width: pw,
+            height: (self.height * pw / self.width).max(1),
+            aa: 1,
+            sampling: Sampling::Grid,
+            .. *self
+        };
+        mandelbrot::histogram(&prepass.sample_rows(0, prepass.height), self.max_iter)
+    }
+
+    /// renders tile_rows rows at a time, streaming each strip into a png so the whole image never sits in memory
+    /// colouring comes from a low resolution pre-pass over the whole view, so strips match each other
+    pub fn render_tiled(&self, tile_rows: usize, path: &str, params: &ViewParams, depth: u8) -> Result<(), String> {
+        if !output::is_png(path) {
+            return Err(String::from("tiled renders can only be written as png"))
+        }
+        if self.adaptive.is_some() {
+            println!("adaptive aa isn't used for tiled renders")
+        }
+        let start = std::time::Instant::now();
+
+        let h = self.prepass_histogram();
+
+        let mut e = output::png_encoder(path, self.width as u32, self.height as u32, params, depth)?
+            .write_header().map_err(|e| e.to_string())?;
+        let mut w = e.stream_writer().map_err(|e| e.to_string())?;
+        let m = self.margin();
+        let mut bytes = Vec::new();
+        for y0 in (0..self.height).step_by(tile_rows.max(1)) {
+            let y1 = (y0 + tile_rows).min(self.height);
+            let s0 = y0.saturating_sub(m);
+            let g = self.sample_rows(s0, (y1 + m).min(self.height));
+            let rows = self.resolve_rows(&g, s0, &h, y0, y1);
+
+            bytes.clear();
+            output::png_bytes(rows.iter(), depth, &mut bytes);
+            std::io::Write::write_all(&mut w, &bytes).map_err(|e| e.to_string())?;
+        }
+        w.finish().map_err(|e| e.to_string())?;
+        println!("tiled render took {}ms", start.elapsed().as_millis());
+        Ok(())
+    }
+
+    fn render_adaptive(&self, threshold: f32) -> Rgb32FImage {
+        self.adaptive_samples(threshold).0
+    }
+    /// one sample per pixel first, then pixels whose colour differs from a neighbour's by more than
+    /// threshold in any channel get more samples in steps, doubling per axis up to aa, for as long as
+    /// each step moves their colour by more than threshold
+    /// colouring uses the table of the first pass. also returns the samples per axis each pixel ended with
+    fn adaptive_samples(&self, threshold: f32) -> (Rgb32FImage, Grid<usize>) {
+        let start = std::time::Instant::now();
+        let (w, hi, m) = (self.width, self.height, self.margin());
+        let count = |x: usize, y: usize, i: usize, j: usize, n: usize| {
+            let (ox, oy) = self.offset(x, y, i, j, n);
+            mandelbrot::do_point_optimised(self.pm.map_f(x as f32 + ox, y as f32 + oy), self.max_iter as usize) as u32
+        };
+        let g = mandelbrot::mt_generate_iter_counts_by(w, hi, self.max_iter, |x, y| {
+            let (ox, oy) = self.offset(x, y, 0, 0, 1);
+            self.pm.map_f(x as f32 + ox, y as f32 + oy)
+        });
+        let h = self.mapping.table(&g, self.max_iter);
+        let srgb = |count: u32| self.palette.colour_offset(h.get(count as usize).copied(), self.palette_offset);
+        let colour = |count: u32| srgb(count).0.map(srgb_to_linear);
+
+        // samples per axis for each pixel, and where a refined pixel's counts start in samples
+        // pixels still on one sample use their count from the first pass
+        let mut levels = vec![1usize; w * hi];
+        let mut starts = vec![0usize; w * hi];
+        let mut samples: Vec<u32> = Vec::new();
+        // pixels with fewer samples count for as much, each sample standing for its share of the pixel
+        let resolve = |x: usize, y: usize, levels: &[usize], starts: &[usize], samples: &[u32]| {
+            let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+            let mut acc = Accum::default();
+            for ny in y.saturating_sub(m)..(y + m + 1).min(hi) {
+                for nx in x.saturating_sub(m)..(x + m + 1).min(w) {
+                    let n = levels[nx + ny * w];
+                    let share = 1.0 / (n * n) as f32;
+                    for k in 0..n * n {
+                        let c = if n == 1 { g.get(nx, ny) } else { samples[starts[nx + ny * w] + k] };
+                        let (ox, oy) = self.offset(nx, ny, k % n, k / n, n);
+                        let wt = self.filter.weight(nx as f32 + ox - cx) * self.filter.weight(ny as f32 + oy - cy) * share;
+                        if wt != 0.0 {
+                            acc.add(colour(c), wt)
+                        }
+                    }
+                }
+            }
+            acc.resolve()
+        };
+        let mut px: Vec<Rgb<f32>> = (0..w * hi).into_par_iter().map(|i| resolve(i % w, i / w, &levels, &starts, &samples)).collect();
+
+        let differs = |x: usize, y: usize, nx: usize, ny: usize| {
+            let (a, b) = (srgb(g.get(x, y)), srgb(g.get(nx, ny)));
+            (0..3).any(|i| (a.0[i] - b.0[i]).abs() > threshold)
+        };
+        let mut refine: Vec<_> = g.iter_coords()
+            .map(|(x, y, _)| (x, y))
+            .filter(|&(x, y)| {
+                (x > 0 && differs(x, y, x - 1, y)) || (x + 1 < w && differs(x, y, x + 1, y))
+                    || (y > 0 && differs(x, y, x, y - 1)) || (y + 1 < hi && differs(x, y, x, y + 1))
+            })
+            .collect();
+
+        let mut n = 1;
+        while n < self.aa && !refine.is_empty() {
+            n = (n * 2).min(self.aa);
+            let counts: Vec<Vec<u32>> = refine.par_iter()
+                .map(|&(x, y)| (0..n * n).map(|k| count(x, y, k % n, k / n, n)).collect())
+                .collect();
+            // counts from earlier steps are left behind in samples, which at most adds a third
+            for (&(x, y), c) in refine.iter().zip(counts) {
+                levels[x + y * w] = n;
+                starts[x + y * w] = samples.len();
+                samples.extend(c)
+            }
+
+            // everything the refined pixels' filters reach needs resolving again
+            let mut touched = vec![false; w * hi];
+            for &(x, y) in &refine {
+                for ny in y.saturating_sub(m)..(y + m + 1).min(hi) {
+                    touched[ny * w + x.saturating_sub(m)..ny * w + (x + m + 1).min(w)].fill(true)
+                }
+            }
+            let update: Vec<_> = (0..w * hi).filter(|&i| touched[i]).map(|i| (i % w, i / w)).collect();
+            let resolved: Vec<_> = update.par_iter().map(|&(x, y)| resolve(x, y, &levels, &starts, &samples)).collect();
+
+            let before: Vec<_> = refine.iter().map(|&(x, y)| px[x + y * w]).collect();
+            for (&(x, y), c) in update.iter().zip(resolved) {
+                px[x + y * w] = c
+            }
+            refine = refine.into_iter().zip(before)
+                .filter(|&((x, y), b)| (0..3).any(|i| (px[x + y * w].0[i] - b.0[i]).abs() > threshold))
+                .map(|(p, _)| p)
+                .collect();
+        }
+
+        let total: usize = levels.iter().map(|n| n * n).sum();
+        let refined = levels.iter().filter(|&&n| n > 1).count();
+        println!("adaptive aa refined {} of {} pixels, {} samples ({:.2} per pixel)", refined, w * hi, total, total as f32 / (w * hi) as f32);
+        println!("rendering took {}ms", start.elapsed().as_millis());
+        let levels = Grid::from_vec(levels, w);
+        (Rgb32FImage::from_fn(w as u32, hi as u32, |x, y| px[x as usize + y as usize * w]), levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn filters_peak_at_centre() {
+        for f in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell, Filter::Lanczos] {
+            assert!(f.weight(0.0) > 0.0, "{}", f.name());
+            assert!(f.weight(0.0) >= f.weight(0.4), "{}", f.name());
+            assert_eq!(f.weight(f.radius() + 0.01), 0.0, "{}", f.name());
+        }
+    }
+    #[test]
+    fn offsets_stay_in_pixel() {
+        for s in [Sampling::Grid, Sampling::Jitter, Sampling::R2] {
+            for n in 0..16 {
+                let (x, y) = s.offset(n, 3 * n, n % 4, n / 4, 4);
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y), "{} {} {}", s.name(), x, y);
+            }
+        }
+    }
+    #[test]
+    fn adaptive_refines_only_edges() {
+        let p = ViewParams { centre: Complex { real: -0.5, imag: 0.0 }, angle: 0.0, width: 32, height: 24, radius: 2.0, aa: 4, filter: Filter::Mitchell, .. ViewParams::default() };
+        let (_, levels) = Renderer::new(&p).unwrap().adaptive_samples(0.01);
+        // inside the cardioid and far outside the set, every neighbour has the same count
+        assert_eq!(levels.get(16, 12), 1);
+        assert_eq!(levels.get(0, 0), 1);
+        // next to -0.75, where the cardioid meets the period 2 bulb
+        assert_eq!(levels.get(13, 12), 4);
+        // no colour differs by more than 1, so nothing is refined
+        let (_, levels) = Renderer::new(&p).unwrap().adaptive_samples(1.0);
+        assert!(levels.iter().all(|n| n == 1));
+    }
+    #[test]
+    fn windows_match_full_render() {
+        // a power of two pixel size, so the cropped mapping lands on exactly the same points
+        let p = ViewParams { centre: Complex { real: -0.5, imag: 0.0 }, angle: 0.0, width: 16, height: 12, radius: 2.0, aa: 2, sampling: Sampling::Jitter, filter: Filter::Tent, .. ViewParams::default() };
+        let r = Renderer::new(&p).unwrap();
+        let h = r.prepass_histogram();
+        let full = r.render_with_histogram(&h);
+        let window = r.render_window(&h, 5, 3, 6, 5);
+        for (x, y, px) in window.enumerate_pixels() {
+            let f = full.get_pixel(x + 5, y + 3);
+            assert!((0..3).all(|i| (px.0[i] - f.0[i]).abs() < 1e-5), "{} {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::*;
+use crate::params::ViewParams;
+use crate::pixelmapper::PixelMapper;
+use crate::render::Renderer;
+use crate::output::to_rgb8_image;
+use crate::pyramid;
+
+const TILE_SIZE: usize = 256;
+const CACHE_TILES: usize = 1024;
+/// connections are handled by this many threads, and at most this many more wait for one
+const WORKERS: usize = 8;
+/// how long a connection can sit without sending before its worker gives up on it
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// most bytes of request line and headers read from one request
+const MAX_HEADER: u64 = 8192;
+/// level 0 is one tile covering the whole set
+const WORLD_CENTRE: Complex = Complex { real: -0.5, imag: 0.0 };
+const WORLD_RADIUS: f32 = 2.0;
+
+/// a tile viewer without any dependencies: drag to pan, scroll to zoom
+/// MAX_ZOOM is filled in with the deepest level the server has tiles for
+const INDEX: &str = r#"<!DOCTYPE html>
+<html><head><title>fractal_window</title>
+<style>
+html, body { height: 100%; margin: 0; background: #000; overflow: hidden; }
+img { position: absolute; width: 256px; height: 256px; user-select: none; }
+</style>
+</head><body><script>
+const MAX_ZOOM = {max_zoom};
+// zoom level, and the view centre in level 0 pixels
+let z = 1, cx = 128, cy = 128, drag = null;
+const tiles = new Map();
+function draw() {
+  const n = 2 ** z, left = cx * n - innerWidth / 2, top = cy * n - innerHeight / 2;
+  const keep = new Set();
+  for (let x = Math.max(0, Math.floor(left / 256)); x <= Math.min(n - 1, Math.floor((left + innerWidth) / 256)); x++) {
+    for (let y = Math.max(0, Math.floor(top / 256)); y <= Math.min(n - 1, Math.floor((top + innerHeight) / 256)); y++) {
+      const k = z + '/' + x + '/' + y;
+      let img = tiles.get(k);
+      if (!img) {
+        img = new Image();
+        img.src = '/tiles/' + k + '.png';
+        img.draggable = false;
+        tiles.set(k, img);
+        document.body.appendChild(img);
+      }
+      img.style.left = (x * 256 - left) + 'px';
+      img.style.top = (y * 256 - top) + 'px';
+      keep.add(k);
+    }
+  }
+  for (const [k, img] of tiles) {
+    if (!keep.has(k)) { img.remove(); tiles.delete(k); }
+  }
+}
+onmousedown = e => drag = [e.clientX, e.clientY];
+onmouseup = () => drag = null;
+onmousemove = e => {
+  if (!drag) return;
+  cx -= (e.clientX - drag[0]) / 2 ** z;
+  cy -= (e.clientY - drag[1]) / 2 ** z;
+  drag = [e.clientX, e.clientY];
+  draw();
+};
+// keeps the point under the cursor where it is
+onwheel = e => {
+  const nz = Math.min(MAX_ZOOM, Math.max(0, z - Math.sign(e.deltaY)));
+  const mx = e.clientX - innerWidth / 2, my = e.clientY - innerHeight / 2;
+  cx += mx / 2 ** z - mx / 2 ** nz;
+  cy += my / 2 ** z - my / 2 ** nz;
+  z = nz;
+  draw();
+};
+onresize = draw;
+draw();
+</script></body></html>
+"#;
+
+/// least recently used cache, small enough that a linear scan of the order is fine
+struct Lru<K, V> {
+    map: HashMap<K, V>,
+    order: VecDeque<K>,
+    cap: usize,
+}
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    fn new(cap: usize) -> Self {
+        Self { map: HashMap::new(), order: VecDeque::new(), cap }
+    }
+    fn get(&mut self, k: &K) -> Option<V> {
+        let v = self.map.get(k)?.clone();
+        if let Some(i) = self.order.iter().position(|o| o == k) {
+            let k = self.order.remove(i).unwrap();
+            self.order.push_back(k);
+        }
+        Some(v)
+    }
+    fn insert(&mut self, k: K, v: V) {
+        if self.map.insert(k.clone(), v).is_none() {
+            self.order.push_back(k);
+        }
+        while self.order.len() > self.cap {
+            if let Some(old) = self.order.pop_front() {
+                self.map.remove(&old);
+            }
+        }
+    }
+}
+
+/// tiles depend on the view's render settings but not its location, which only the json endpoint reports
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct TileKey {
+    z: u32,
+    x: usize,
+    y: usize,
+    max_iter: u32,
+    aa: usize,
+    sampling: &'static str,
+    filter: &'static str,
+    palette: &'static str,
+    palette_offset: u32,
+    mapping: &'static str,
+}
+
+/// a shared colouring table
+type Table = Arc<Vec<f32>>;
+
+struct Server {
+    view: Arc<Mutex<ViewParams>>,
+    /// deepest level with tiles, past it f32 can't tell pixels apart
+    max_level: u32,
+    tiles: Mutex<Lru<TileKey, Arc<Vec<u8>>>>,
+    /// shared colouring per max_iter and mapping, so tiles match across levels
+    histograms: Mutex<HashMap<(u32, &'static str), Table>>,
+}
+
+/// serves tiles and the view on localhost from a background thread
+/// the view is read from the shared params on every request, so the controller keeps it current
+pub fn serve(port: u16, view: Arc<Mutex<ViewParams>>) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    let server = Arc::new(Server {
+        view,
+        max_level: pyramid::max_level(WORLD_CENTRE, WORLD_RADIUS, TILE_SIZE),
+        tiles: Mutex::new(Lru::new(CACHE_TILES)),
+        histograms: Mutex::new(HashMap::new()),
+    });
+    println!("serving on http://127.0.0.1:{}/", port);
+    let (send, receive) = std::sync::mpsc::sync_channel::<TcpStream>(WORKERS);
+    let receive = Arc::new(Mutex::new(receive));
+    for _ in 0..WORKERS {
+        let (server, receive) = (server.clone(), receive.clone());
+        std::thread::spawn(move || {
+            loop {
+                // the lock is only held while waiting, not while handling
+                let next = receive.lock().unwrap().recv();
+                match next {
+                    Ok(s) => server.handle(s),
+                    Err(_) => break
+                }
+            }
+        });
+    }
+    std::thread::spawn(move || {
+        for s in listener.incoming().flatten() {
+            if send.send(s).is_err() {
+                break
+            }
+        }
+    });
+    Ok(())
+}
+
+impl Server {
+    fn handle(&self, mut s: TcpStream) {
+        // idle connections (ie. browsers' speculative ones) would otherwise hold a worker forever
+        if s.set_read_timeout(Some(READ_TIMEOUT)).and_then(|_| s.set_write_timeout(Some(READ_TIMEOUT))).is_err() {
+            return
+        }
+        let mut reader = BufReader::new((&s).take(MAX_HEADER));
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() || line.is_empty() {
+            return
+        }
+        // the headers don't change anything, but the client expects them read before the response
+        let mut header = String::new();
+        loop {
+            header.clear();
+            match reader.read_line(&mut header) {
+                Ok(n) if n > 0 && !header.trim().is_empty() => continue,
+                Ok(_) => break,
+                Err(_) => return
+            }
+        }
+        if reader.get_ref().limit() == 0 {
+            let _ = respond(&mut s, 431, "text/plain", b"request headers too large");
+            return
+        }
+        let mut parts = line.split_ascii_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+        let response = if method != "GET" {
+            Err((405, "only GET is supported"))
+        }
+        else if path == "/" {
+            Ok(("text/html", INDEX.replace("{max_zoom}", &self.max_level.to_string()).into_bytes()))
+        }
+        else if path == "/view.json" {
+            Ok(("application/json", self.view_json().into_bytes()))
+        }
+        else if let Some(tile) = path.strip_prefix("/tiles/").and_then(|t| t.strip_suffix(".png")) {
+            self.tile(tile).map(|t| ("image/png", t.to_vec()))
+        }
+        else {
+            Err((404, "not found"))
+        };
+        let _ = match response {
+            Ok((kind, body)) => respond(&mut s, 200, kind, &body),
+            Err((code, msg)) => respond(&mut s, code, "text/plain", msg.as_bytes()),
+        };
+    }
+
+    fn view_json(&self) -> String {
+        let p = self.view.lock().unwrap().clone();
+        format!(
+            "{{\"centre\":[{},{}],\"radius\":{},\"angle\":{},\"resolution\":[{},{}],\"formula\":\"{}\",\"palette\":\"{}\",\"max_iter\":{},\"aa\":{},\"share\":\"{}\"}}",
+            p.centre.real, p.centre.imag, p.radius, p.angle, p.width, p.height,
+            p.formula.name(), p.palette.name(), p.max_iter, p.aa, p.to_share_string()
+        )
+    }
+
+    fn tile(&self, t: &str) -> Result<Arc<Vec<u8>>, (u16, &'static str)> {
+        let mut i = t.split('/').map(|v| v.parse::<usize>().ok());
+        let (Some(Some(z)), Some(Some(x)), Some(Some(y)), None) = (i.next(), i.next(), i.next(), i.next()) else {
+            return Err((400, "tiles are /tiles/z/x/y.png"))
+        };
+        if z > self.max_level as usize || x >> z != 0 || y >> z != 0 {
+            return Err((404, "no such tile"))
+        }
+        let z = z as u32;
+
+        let p = self.view.lock().unwrap().clone();
+        let max_iter = u32::try_from(p.max_iter).map_err(|_| (500, "max_iter too large"))?;
+        let key = TileKey { z, x, y, max_iter, aa: p.aa, sampling: p.sampling.name(), filter: p.filter.name(),
+            palette: p.palette.name(), palette_offset: p.palette_offset.to_bits(), mapping: p.mapping.name() };
+        if let Some(t) = self.tiles.lock().unwrap().get(&key) {
+            return Ok(t)
+        }
+
+        let n = (TILE_SIZE << z) as u32;
+        let r = Renderer {
+            pm: PixelMapper::new_radx(WORLD_CENTRE, WORLD_RADIUS, 0.0, n, n),
+            width: n as usize,
+            height: n as usize,
+            max_iter,
+            aa: p.aa.max(1),
+            sampling: p.sampling,
+            filter: p.filter,
+            adaptive: None,
+            palette: p.palette,
+            palette_offset: p.palette_offset,
+            mapping: p.mapping,
+            origin: (0, 0),
+        };
+        let h = self.histogram(max_iter, &r);
+        let img = r.render_window(&h, x * TILE_SIZE, y * TILE_SIZE, TILE_SIZE, TILE_SIZE);
+        let mut png = Vec::new();
+        to_rgb8_image(&img)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .map_err(|_| (500, "couldn't encode tile"))?;
+
+        let png = Arc::new(png);
+        self.tiles.lock().unwrap().insert(key, png.clone());
+        Ok(png)
+    }
+
+    fn histogram(&self, max_iter: u32, r: &Renderer) -> Table {
+        let key = (max_iter, r.mapping.name());
+        if let Some(h) = self.histograms.lock().unwrap().get(&key) {
+            return h.clone()
+        }
+        let world = Renderer {
+            pm: PixelMapper::new_radx(WORLD_CENTRE, WORLD_RADIUS, 0.0, TILE_SIZE as u32, TILE_SIZE as u32),
+            width: TILE_SIZE,
+            height: TILE_SIZE,
+            .. *r
+        };
+        let h = Arc::new(world.prepass_histogram());
+        self.histograms.lock().unwrap().insert(key, h.clone());
+        h
+    }
+}
+
+fn respond(s: &mut TcpStream, code: u16, kind: &str, body: &[u8]) -> std::io::Result<()> {
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(s, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", code, reason, kind, body.len())?;
+    s.write_all(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn lru_evicts_oldest() {
+        let mut c = Lru::new(2);
+        c.insert(1, 'a');
+        c.insert(2, 'b');
+        c.get(&1);
+        c.insert(3, 'c');
+        assert_eq!(c.get(&2), None);
+        assert_eq!(c.get(&1), Some('a'));
+        assert_eq!(c.get(&3), Some('c'));
+    }
+}
@@ -81,44 +81,134 @@ use image::Rgb;
 
 const DARK_BLUE: Rgb<u8> = Rgb([4, 4, 130]);
 const WHITE: Rgb<u8> = Rgb([255; 3]);
+const BLACK: Rgb<u8> = Rgb([0; 3]);
+const RED: Rgb<u8> = Rgb([200, 20, 0]);
+const YELLOW: Rgb<u8> = Rgb([255, 210, 40]);
 
-pub fn h_palette(cic: Option<f32>) -> Rgb<u8> {
-    if let Some(cic) = cic {
-        lerp_colour(cic.powi(2), DARK_BLUE, WHITE)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Palette {
+    /// dark blue to white
+    Blue,
+    /// black through red and yellow to white
+    Fire,
+    /// black to white
+    Grey,
+}
+impl Palette {
+    pub fn name(self) -> &'static str {
+        match self {
+            Palette::Blue => "blue",
+            Palette::Fire => "fire",
+            Palette::Grey => "grey",
+        }
     }
-    else {
-        Rgb([0, 0, 0])
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "blue" => Some(Palette::Blue),
+            "fire" => Some(Palette::Fire),
+            "grey" => Some(Palette::Grey),
+            _ => None
+        }
+    }
+    /// as colour, with the position moved along the palette by offset
+    /// past either end it reflects back, so cycling the offset never jumps
+    pub fn colour_offset(self, cic: Option<f32>, offset: f32) -> Rgb<f32> {
+        self.colour(cic.map(|t| 1.0 - ((t + offset).rem_euclid(2.0) - 1.0).abs()))
+    }
+    /// colour for a position in the colouring table, none is inside the set (black)
+    /// colours are floats from 0 to 1, still srgb encoded
+    pub fn colour(self, cic: Option<f32>) -> Rgb<f32> {
+        let Some(t) = cic else {
+            return Rgb([0.0; 3])
+        };
+        match self {
+            Palette::Blue => lerp_colour(t.powi(2), DARK_BLUE, WHITE),
+            Palette::Fire => {
+                let t = t.clamp(0.0, 1.0) * 3.0;
+                let (a, b) = [(BLACK, RED), (RED, YELLOW), (YELLOW, WHITE), (WHITE, WHITE)][t as usize];
+                lerp_colour(t.fract(), a, b)
+            }
+            Palette::Grey => lerp_colour(t, BLACK, WHITE),
+        }
     }
 }
 
+/// colours are floats from 0 to 1, still srgb encoded
+pub fn h_palette(cic: Option<f32>) -> Rgb<f32> {
+    Palette::Blue.colour(cic)
+}
+
 pub fn lerp(t: f32, a: f32, b: f32) -> f32 {
     a * (1.0 - t) + b * t
 }
-pub fn lerp_u8(t: f32, a: u8, b: u8) -> u8 {
-    let a = a as f32;
-    let b = b as f32;
-    (a * (1.0 - t) + b * t) as u8
+pub fn lerp_colour(t: f32, a: Rgb<u8>, b: Rgb<u8>) -> Rgb<f32> {
+    Rgb(std::array::from_fn(|i| lerp(t, a.0[i] as f32, b.0[i] as f32) / 255.0))
+}
+
+pub fn to_rgb8(c: Rgb<f32>) -> Rgb<u8> {
+    Rgb(c.0.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8))
+}
+pub fn to_rgb16(c: Rgb<f32>) -> Rgb<u16> {
+    Rgb(c.0.map(|v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16))
+}
+
+/// srgb transfer function, both ways
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    }
+    else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    }
+    else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
 }
-pub fn lerp_colour(t: f32, a: Rgb<u8>, b: Rgb<u8>) -> Rgb<u8> {
-    let r = lerp_u8(t, a.0[0], b.0[0]);
-    let g = lerp_u8(t, a.0[1], b.0[1]);
-    let b = lerp_u8(t, a.0[2], b.0[2]);
-    Rgb([r, g, b])
+
+/// weighted sum in linear light, so thin bright or dark details keep their weight
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Accum {
+    sum: [f32; 3],
+    weight: f32,
 }
+impl Accum {
+    /// colour is linear
+    pub fn add(&mut self, colour: [f32; 3], weight: f32) {
+        (0..3).for_each(|i| self.sum[i] += colour[i] * weight);
+        self.weight += weight;
+    }
+    /// back to srgb encoded
+    pub fn resolve(&self) -> Rgb<f32> {
+        if self.weight <= 0.0 {
+            return Rgb([0.0; 3])
+        }
+        Rgb(self.sum.map(|v| linear_to_srgb((v / self.weight).clamp(0.0, 1.0))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn average_in_linear_light() {
+        let mut half = Accum::default();
+        [Rgb([0.0; 3]), Rgb([1.0; 3])].iter().for_each(|c| half.add(c.0.map(srgb_to_linear), 1.0));
+        assert_eq!(to_rgb8(half.resolve()), Rgb([188; 3]));
 
-pub fn average_colour(c: impl IntoIterator<Item = Rgb<u8>>) -> Rgb<u8> {
-    let mut count = 0u16;
-    let (r, g, b) = c.into_iter().fold((0, 0, 0), |acc, c| {
-        count += 1;
-        (
-            acc.0 + c.0[0] as u16,
-            acc.1 + c.0[1] as u16,
-            acc.2 + c.0[2] as u16,
-        )
-    });
-    Rgb([
-        (r / count) as u8,
-        (g / count) as u8,
-        (b / count) as u8
-    ])
+        // 32x32 samples used to overflow the old u16 sum
+        let mut white = Accum::default();
+        (0..32 * 32).for_each(|_| white.add([1.0; 3], 1.0));
+        assert_eq!(to_rgb8(white.resolve()), Rgb([255; 3]));
+    }
+    #[test]
+    fn srgb_round_trip() {
+        for v in [0.0, 0.002, 0.04, 0.5, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-6);
+        }
+    }
 }
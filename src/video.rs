@@ -0,0 +1,134 @@
+// animations written straight to one file instead of a numbered image sequence
+// y4m is uncompressed 4:4:4 for piping into an encoder, gif and apng are for short loops
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+use image::Rgb32FImage;
+use image::codecs::gif::{GifEncoder, Repeat};
+
+use crate::output::has_extension;
+
+enum Encoder {
+    Y4m(BufWriter<File>),
+    /// with the frame delay in hundredths of a second, and the path to check the trailer in
+    Gif(GifEncoder<BufWriter<File>>, u32, String),
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+pub struct Video {
+    encoder: Encoder,
+}
+
+/// whether a path is one of the formats Video writes
+pub fn is_video(path: &str) -> bool {
+    ["y4m", "gif", "apng"].iter().any(|e| has_extension(path, e))
+}
+
+impl Video {
+    /// frames is needed up front by apng. looping gifs and apngs repeat forever, otherwise they play once
+    pub fn create(path: &str, width: u32, height: u32, frames: u32, fps: u32, looping: bool) -> Result<Self, String> {
+        let f = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        let encoder = if has_extension(path, "y4m") {
+            let mut f = f;
+            writeln!(f, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, fps).map_err(|e| e.to_string())?;
+            Encoder::Y4m(f)
+        }
+        else if has_extension(path, "gif") {
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(String::from("gifs can be at most 65535 pixels across"))
+            }
+            // speed 10 is the quantiser's default, 1 takes seconds a frame at full hd
+            let mut e = GifEncoder::new_with_speed(f, 10);
+            // a gif without a repeat count plays once
+            if looping {
+                e.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?
+            }
+            let delay = (100.0 / fps.max(1) as f32).round().max(1.0) as u32;
+            if 100 % fps.max(1) != 0 {
+                println!("gifs time frames in hundredths of a second, so {} fps plays at {:.1}", fps, 100.0 / delay as f32)
+            }
+            Encoder::Gif(e, delay, path.to_owned())
+        }
+        else if has_extension(path, "apng") {
+            let mut e = png::Encoder::new(f, width, height);
+            e.set_color(png::ColorType::Rgb);
+            e.set_depth(png::BitDepth::Eight);
+            e.set_animated(frames, if looping { 0 } else { 1 }).map_err(|e| e.to_string())?;
+            e.set_frame_delay(1, u16::try_from(fps).map_err(|_| "fps too high for apng")?).map_err(|e| e.to_string())?;
+            Encoder::Apng(e.write_header().map_err(|e| e.to_string())?)
+        }
+        else {
+            return Err(format!("don't know how to write video to {}", path))
+        };
+        Ok(Self { encoder })
+    }
+
+    pub fn write_frame(&mut self, i: &Rgb32FImage) -> Result<(), String> {
+        match &mut self.encoder {
+            Encoder::Y4m(f) => {
+                let planes = ycbcr_planes(i);
+                f.write_all(b"FRAME\n").map_err(|e| e.to_string())?;
+                planes.iter().try_for_each(|p| f.write_all(p)).map_err(|e| e.to_string())
+            }
+            Encoder::Gif(e, delay, _) => {
+                let rgba = image::DynamicImage::ImageRgb8(crate::output::to_rgb8_image(i)).into_rgba8();
+                let delay = image::Delay::from_numer_denom_ms(*delay * 10, 1);
+                e.encode_frame(image::Frame::from_parts(rgba, 0, 0, delay)).map_err(|e| e.to_string())
+            }
+            Encoder::Apng(w) => {
+                let mut data = Vec::new();
+                crate::output::png_bytes(i.pixels(), 8, &mut data);
+                w.write_image_data(&data).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self.encoder {
+            Encoder::Y4m(mut f) => f.flush().map_err(|e| e.to_string()),
+            // the encoder only writes its trailer and flushes on drop, where errors are lost, so check it landed
+            Encoder::Gif(e, _, path) => {
+                drop(e);
+                let mut last = [0];
+                File::open(&path)
+                    .and_then(|mut f| f.seek(SeekFrom::End(-1)).and_then(|_| f.read_exact(&mut last)))
+                    .map_err(|e| e.to_string())?;
+                if last != [0x3b] {
+                    return Err(format!("{} wasn't finished", path))
+                }
+                Ok(())
+            }
+            Encoder::Apng(w) => w.finish().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// full resolution y, cb and cr planes, bt.601 limited range as y4m readers assume
+fn ycbcr_planes(i: &Rgb32FImage) -> [Vec<u8>; 3] {
+    let n = (i.width() * i.height()) as usize;
+    let mut planes = [Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n)];
+    for p in i.pixels() {
+        let [r, g, b] = p.0.map(|v| v.clamp(0.0, 1.0));
+        let yuv = [
+            16.0 + 65.481 * r + 128.553 * g + 24.966 * b,
+            128.0 - 37.797 * r - 74.203 * g + 112.0 * b,
+            128.0 + 112.0 * r - 93.786 * g - 18.214 * b,
+        ];
+        (0..3).for_each(|c| planes[c].push(yuv[c].round() as u8));
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn ycbcr_range() {
+        let i = Rgb32FImage::from_fn(2, 1, |x, _| image::Rgb([x as f32; 3]));
+        let [y, cb, cr] = ycbcr_planes(&i);
+        assert_eq!(y, [16, 235]);
+        assert_eq!(cb, [128, 128]);
+        assert_eq!(cr, [128, 128]);
+    }
+}